@@ -0,0 +1,122 @@
+use datalink::{id::ID, prelude::*};
+
+use crate::{database::Database, error::Result, primitive::Primitive, storeddata::StoredData};
+
+/// Async facade over a [`Database`].
+///
+/// Every call runs the blocking SQLite work on [`tokio::task::spawn_blocking`],
+/// so the runtime isn't blocked while waiting for the connection.
+#[derive(Debug, Clone)]
+pub struct AsyncDatabase {
+    db: Database,
+}
+
+impl AsyncDatabase {
+    #[inline]
+    #[must_use]
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// The wrapped synchronous database.
+    #[inline]
+    #[must_use]
+    pub fn inner(&self) -> &Database {
+        &self.db
+    }
+
+    /// Runs `f` with the wrapped database on the blocking thread pool.
+    ///
+    /// Panics inside `f` are resumed on the awaiting task.
+    pub async fn run<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&Database) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    #[inline]
+    pub async fn init(&self) -> Result {
+        self.run(Database::init).await
+    }
+
+    #[cfg(feature = "migrations")]
+    #[inline]
+    pub async fn migrate(&self) -> Result {
+        self.run(Database::migrate).await
+    }
+
+    #[inline]
+    pub async fn store<D>(&self, data: D) -> Result<StoredData>
+    where
+        D: Data + Unique + Send + 'static,
+    {
+        self.run(move |db| db.store(&data)).await
+    }
+
+    #[inline]
+    pub async fn get_or_store<D>(&self, data: D) -> Result<StoredData>
+    where
+        D: Data + Unique + Send + 'static,
+    {
+        self.run(move |db| db.get_or_store(&data)).await
+    }
+
+    #[inline]
+    pub async fn exists(&self, id: ID) -> Result<bool> {
+        self.run(move |db| db.exists(id)).await
+    }
+
+    /// Doesn't touch the database, see [`Database::get`].
+    #[inline]
+    #[must_use]
+    pub fn get(&self, id: ID) -> StoredData {
+        self.db.get(id)
+    }
+
+    #[inline]
+    pub async fn value(&self, id: ID) -> Result<Option<Primitive>> {
+        self.run(move |db| db.get(id).value()).await
+    }
+
+    #[inline]
+    pub async fn entries(&self, id: ID) -> Result<Vec<(Option<StoredData>, StoredData)>> {
+        self.run(move |db| db.get(id).entries()).await
+    }
+}
+
+impl From<Database> for AsyncDatabase {
+    #[inline]
+    fn from(db: Database) -> Self {
+        Self::new(db)
+    }
+}
+
+#[cfg(all(test, feature = "random"))]
+mod tests {
+    use super::*;
+    use datalink::data::DataExt;
+
+    #[tokio::test]
+    async fn store_and_read() {
+        let db = AsyncDatabase::new(Database::open_in_memory().unwrap());
+        db.init().await.unwrap();
+
+        let data = "hello".into_unique_random();
+        let id = data.id();
+        let stored = db.store(data).await.unwrap();
+        assert_eq!(stored.id, id);
+
+        assert!(db.exists(id).await.unwrap());
+        assert_eq!(db.value(id).await.unwrap(), Some(Primitive::from("hello")));
+
+        let list = vec![1, 2, 3].into_unique_random();
+        let id = list.id();
+        db.store(list).await.unwrap();
+        assert_eq!(db.entries(id).await.unwrap().len(), 3);
+    }
+}
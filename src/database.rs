@@ -3,32 +3,111 @@ use datalink::{
     prelude::*,
     query::Query,
 };
-use rusqlite::{params, Connection, Transaction};
+use rusqlite::{
+    backup::Backup, params, types::ValueRef, Batch, Connection, DatabaseName, OpenFlags, Params,
+    Row, Transaction, TransactionBehavior,
+};
 use std::{
-    path::Path,
-    sync::{Arc, Mutex},
+    cell::RefCell,
+    cmp::Ordering,
+    collections::VecDeque,
+    hash::Hasher,
+    iter::FusedIterator,
+    ops::{ControlFlow, Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    time::Duration,
 };
 
 use crate::{
-    error::Result,
-    query::{build_links, QueryContext, SQLBuilder, SqlFragment},
+    error::{Error, Result},
+    primitive::{self, Primitive, PrimitiveType},
+    query::{
+        build_links, build_links_with, for_each_row, with_cursor, QueryContext, SQLBuilder,
+        SqlFragment,
+    },
+    schema::Schema,
     storeddata::StoredData,
-    util::SqlID,
+    util::{Fnv1a, IdStrategy, SeededIds, SqlID, WithId},
 };
 
-const INSERT_VALUES: &str = "INSERT INTO `values` (uuid, bool, u8, i8, u16, i16, u32, i32, u64, i64, f32, f64, str)
-VALUES (?, ? ,? ,? ,? ,? ,? ,? ,? ,? ,? ,? ,?)
-ON CONFLICT(uuid)
-DO UPDATE
-SET bool=excluded.bool, u8=excluded.u8, i8=excluded.i8, u16=excluded.u16, i16=excluded.i16, u32=excluded.u32, i32=excluded.i32, u64=excluded.u64, i64=excluded.i64, f32=excluded.f32, f64=excluded.f64, str=excluded.str;";
-const INSERT_LINK_KEYED: &str = "INSERT INTO `links` (source_uuid, target_uuid, key_uuid)
+pub use crate::query::QueryCursor;
+
+const INSERT_LINK_KEYED: &str = "INSERT INTO `links` (`source_uuid`, `target_uuid`, `key_uuid`)
 VALUES (?, ?, ?);";
-const INSERT_LINK_UNKEYED: &str = "INSERT INTO `links` (source_uuid, target_uuid)
+const INSERT_LINK_UNKEYED: &str = "INSERT INTO `links` (`source_uuid`, `target_uuid`)
 VALUES (?, ?);";
 
-#[derive(Debug, Clone)]
+/// Upserts the id followed by the values in the order of [`PrimitiveType::ALL`]
+fn insert_values() -> &'static str {
+    static SQL: OnceLock<String> = OnceLock::new();
+    SQL.get_or_init(|| {
+        let placeholders = ["?"; PrimitiveType::ALL.len()].join(", ");
+        format!(
+            "INSERT INTO `values` (`uuid`, {})
+VALUES (?, {placeholders})
+ON CONFLICT(`uuid`)
+DO UPDATE
+SET {}, updated_at=strftime('%Y-%m-%d %H:%M:%f', 'now');",
+            primitive::all_columns(),
+            primitive::set_all_excluded()
+        )
+    })
+}
+
+/// Indexes not needed for storing, dropped during [`Database::bulk_load`]
+const BULK_DROP_INDEXES: &str = "DROP INDEX IF EXISTS `data_strs`;
+DROP INDEX IF EXISTS `links_source`;
+DROP INDEX IF EXISTS `links_key`;
+DROP INDEX IF EXISTS `links_target`;
+DROP INDEX IF EXISTS `links_keyed`;";
+const BULK_CREATE_INDEXES: &str = "CREATE INDEX IF NOT EXISTS `data_strs` ON `values` (`str`);
+CREATE INDEX IF NOT EXISTS `links_source` ON `links` (`source_uuid`);
+CREATE INDEX IF NOT EXISTS `links_key` ON `links` (`key_uuid`);
+CREATE INDEX IF NOT EXISTS `links_target` ON `links` (`target_uuid`);
+CREATE INDEX IF NOT EXISTS `links_keyed` ON `links` (`source_uuid`, `key_uuid`);";
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_ONLY_FLAGS: OpenFlags = OpenFlags::SQLITE_OPEN_READ_ONLY
+    .union(OpenFlags::SQLITE_OPEN_URI)
+    .union(OpenFlags::SQLITE_OPEN_NO_MUTEX);
+
+/// Pragmas accepted by [`Database::apply_pragmas`]
+pub const ALLOWED_PRAGMAS: &[&str] = &[
+    "auto_vacuum",
+    "automatic_index",
+    "busy_timeout",
+    "cache_size",
+    "cache_spill",
+    "foreign_keys",
+    "journal_mode",
+    "journal_size_limit",
+    "locking_mode",
+    "mmap_size",
+    "secure_delete",
+    "synchronous",
+    "temp_store",
+    "wal_autocheckpoint",
+];
+
+/// Log target of the statements logged after [`Database::set_trace`]
+pub const SQL_LOG_TARGET: &str = "datalink_sqlite::sql";
+
+/// Handle to a SQLite database storing datalink data.
+///
+/// All clones share one [`Connection`] behind a mutex, so a `Database`
+/// is `Send + Sync` and can be shared across threads without any
+/// additional rusqlite features. Access is serialized by the mutex.
+#[derive(Clone)]
 pub struct Database {
     pub(crate) conn: Arc<Mutex<Connection>>,
+    pub(crate) schema: Arc<Schema>,
+    id_strategy: IdStrategy,
+    seed: Option<Arc<SeededIds>>,
+    tx_behavior: TransactionBehavior,
 }
 
 impl Database {
@@ -36,23 +115,132 @@ impl Database {
     pub fn new(conn: Connection) -> Self {
         Self {
             conn: Arc::new(Mutex::new(conn)),
+            schema: Arc::default(),
+            id_strategy: IdStrategy::default(),
+            seed: None,
+            tx_behavior: TransactionBehavior::Deferred,
+        }
+    }
+
+    /// Uses the table and column names of `schema` for all reads and writes,
+    /// see [`Schema`].
+    #[inline]
+    #[must_use]
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Arc::new(schema);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Uses `strategy` for minting the ids of stored data without one.
+    #[inline]
+    #[must_use]
+    pub fn with_id_strategy(mut self, strategy: IdStrategy) -> Self {
+        self.id_strategy = strategy;
+        self
+    }
+
+    /// Mints ids from a generator seeded with `seed` instead of the thread's
+    /// random generator, so storing the same data mints the same ids.
+    ///
+    /// Meant for tests. Clones share the generator, and [`IdStrategy::TimeOrdered`]
+    /// still adds the current time. Works without the `random` feature.
+    #[inline]
+    #[must_use]
+    pub fn with_random_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(Arc::new(SeededIds::new(seed)));
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn id_strategy(&self) -> IdStrategy {
+        self.id_strategy
+    }
+
+    /// Begins the transactions of [`Database::store`], [`Database::transaction_with`]
+    /// and [`Database::bulk_load`] with `behavior`.
+    ///
+    /// The default deferred transactions only take the write lock on their first
+    /// write, which can fail with `SQLITE_BUSY` midway when other connections write
+    /// concurrently. [`TransactionBehavior::Immediate`] takes it right away.
+    #[inline]
+    #[must_use]
+    pub fn with_transaction_behavior(mut self, behavior: TransactionBehavior) -> Self {
+        self.tx_behavior = behavior;
+        self
+    }
+
+    /// Wraps `conn` after applying the recommended connection settings:
+    ///
+    /// - `PRAGMA foreign_keys = ON`
+    /// - a busy timeout of 5 seconds
+    #[inline]
+    pub fn with_pragmas(conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(Self::new(conn))
+    }
+
+    /// Enables or disables `PRAGMA foreign_keys`.
+    ///
+    /// SQLite ignores this while a transaction is open, so it can't be
+    /// changed from within [`Database::transaction_with`] or [`Database::bulk_load`].
+    #[inline]
+    pub fn with_foreign_keys(&self, on: bool) -> Result {
+        let conn = self.lock()?;
+        conn.pragma_update(None, "foreign_keys", on)?;
+        Ok(())
+    }
+
+    /// Sets several pragmas at once, in order.
+    ///
+    /// Pragma names can't be bound as parameters, so only the ones in
+    /// [`ALLOWED_PRAGMAS`] are accepted and nothing is applied otherwise.
+    pub fn apply_pragmas(&self, pragmas: &[(&str, &str)]) -> Result {
+        if let Some((name, _)) = pragmas
+            .iter()
+            .find(|(name, _)| !ALLOWED_PRAGMAS.contains(&name.to_ascii_lowercase().as_str()))
+        {
+            return Err(Error::InvalidPragma((*name).to_owned()));
         }
+
+        let conn = self.lock()?;
+        for (name, value) in pragmas {
+            conn.pragma_update(None, name, value)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn foreign_keys_enabled(&self) -> Result<bool> {
+        let conn = self.lock()?;
+        let enabled = conn.pragma_query_value(None, "foreign_keys", |r| r.get(0))?;
+        Ok(enabled)
     }
 
     #[inline]
     pub fn init(&self) -> Result {
         log::info!("Initializing");
+        self.check_read_only()?;
         if self.is_ready() {
             log::info!("Already initialized");
             return Ok(());
         }
 
-        let mut conn = self.conn.lock().unwrap();
+        let mut conn = self.lock()?;
         let tx = conn.transaction()?;
 
-        tx.execute_batch(include_str!("migrations/1.sql"))?;
-        tx.execute_batch(include_str!("migrations/2a.sql"))?;
-        tx.execute_batch(include_str!("migrations/2b.sql"))?;
+        let schema = &self.schema;
+        tx.execute_batch(&schema.render(include_str!("migrations/1.sql")))?;
+        tx.execute_batch(&schema.render(include_str!("migrations/2a.sql")))?;
+        tx.execute_batch(&schema.render(include_str!("migrations/2b.sql")))?;
+        tx.execute_batch(&schema.render(include_str!("migrations/3.sql")))?;
 
         tx.commit()?;
         drop(conn);
@@ -61,281 +249,3058 @@ impl Database {
         Ok(())
     }
 
+    /// Migrates the schema to the version of this crate.
+    ///
+    /// Fails with [`Error::SchemaTooNew`] for a schema written by a newer version.
     #[cfg(feature = "migrations")]
     #[inline]
     pub fn migrate(&self) -> Result {
         log::info!("Migrating");
-        crate::migration::Migrations::new(self).run_all()
+        self.check_read_only()?;
+        if self.compatibility()? == Compatibility::Newer {
+            return Err(Error::SchemaTooNew);
+        }
+        crate::migration::Migrations::new(self).run_all()?;
+        self.optimize()
+    }
+
+    /// Runs `PRAGMA optimize` to refresh the query planner statistics.
+    ///
+    /// Cheap enough to call periodically on long-lived connections
+    /// and before closing them.
+    #[inline]
+    pub fn optimize(&self) -> Result {
+        let conn = self.lock()?;
+        conn.execute_batch("PRAGMA optimize;")?;
+        Ok(())
     }
 
     #[inline]
     pub fn schema_version(&self) -> Result<i32> {
         const SQL: &str = "SELECT user_version FROM pragma_user_version();";
 
-        let conn = self.conn.lock().unwrap();
+        let conn = self.lock()?;
         let version = conn.query_row(SQL, [], |r| r.get(0))?;
         Ok(version)
     }
 
-    #[inline]
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Connection::open(path).map(Self::new).map_err(From::from)
+    /// Inspects the tables and the recorded version, without changing anything.
+    ///
+    /// Tells tools whether a file can be initialized, has to be migrated or
+    /// should be left alone.
+    pub fn detect_schema(&self) -> Result<SchemaState> {
+        const OBJECTS: &str = "SELECT `name` FROM sqlite_master WHERE `name` NOT LIKE 'sqlite_%'";
+
+        let conn = self.lock()?;
+        let objects = conn
+            .prepare(OBJECTS)?
+            .query_map([], |r| r.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if objects.is_empty() {
+            return Ok(SchemaState::Empty);
+        }
+        let tables = [&self.schema.values, &self.schema.links];
+        if !tables.iter().all(|t| objects.contains(*t)) {
+            return Ok(SchemaState::NotDatalink);
+        }
+
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+        let state = match detect_version(&conn, &self.schema)? {
+            Some(v) if v != version => SchemaState::Unknown,
+            Some(v) if v == crate::schema_version!() => SchemaState::Current,
+            Some(v) if v > 0 && v < crate::schema_version!() => SchemaState::Legacy(v),
+            _ => SchemaState::Unknown,
+        };
+        Ok(state)
     }
 
+    /// Compares the schema version of the database to the one of this crate.
+    ///
+    /// Data written by a newer version can still be read,
+    /// but storing fails with [`Error::SchemaTooNew`].
     #[inline]
-    pub fn open_in_memory() -> Result<Self> {
-        Connection::open_in_memory()
-            .map(Self::new)
-            .map_err(From::from)
+    pub fn compatibility(&self) -> Result<Compatibility> {
+        Ok(Compatibility::of(self.schema_version()?))
     }
 
+    /// Sets `PRAGMA page_size` to `bytes`, a power of two from 512 to 65536.
+    ///
+    /// Databases with content are rebuilt with a `VACUUM` for the new size to
+    /// take effect. A database in WAL mode keeps its page size,
+    /// so this fails with [`Error::PageSizeInWal`].
     #[inline]
-    pub fn store<D: Data + Unique>(&self, data: &D) -> Result<StoredData> {
-        debug_assert!(self.is_ready());
-        let mut conn = self.conn.lock().unwrap();
-        let tx = conn.transaction()?;
-        Self::store_inner(&tx, data)?;
-        tx.commit()?;
-        Ok(StoredData {
-            db: self.clone(),
-            id: data.id(),
-        })
+    pub fn set_page_size(&self, bytes: u32) -> Result {
+        const HAS_CONTENT: &str = "SELECT EXISTS (SELECT 1 FROM sqlite_master)";
+
+        if !(512..=65536).contains(&bytes) || !bytes.is_power_of_two() {
+            return Err(Error::InvalidPageSize(bytes));
+        }
+        let conn = self.lock()?;
+        let journal_mode: String = conn.pragma_query_value(None, "journal_mode", |r| r.get(0))?;
+        if journal_mode.eq_ignore_ascii_case("wal") {
+            return Err(Error::PageSizeInWal);
+        }
+        conn.pragma_update(None, "page_size", bytes)?;
+        let has_content: bool = conn.query_row(HAS_CONTENT, [], |r| r.get(0))?;
+        if has_content {
+            conn.execute_batch("VACUUM;")?;
+        }
+        Ok(())
     }
 
+    /// Sets `PRAGMA cache_size`, the maximum size of the page cache of this connection.
     #[inline]
-    fn store_inner<D: Data + Unique>(tx: &Transaction, data: &D) -> Result<()> {
-        use datalink::data::DataExt;
-        let mut stmt = tx.prepare_cached(INSERT_VALUES)?;
-
-        let id = data.id().into();
-        let values = data.all_values();
+    pub fn set_cache_size(&self, size: CacheSize) -> Result {
+        let conn = self.lock()?;
+        conn.pragma_update(None, "cache_size", size.as_pragma())?;
+        Ok(())
+    }
 
-        stmt.execute(params![
-            id,
-            values.as_bool(),
-            values.as_u8(),
-            values.as_i8(),
-            values.as_u16(),
-            values.as_i16(),
-            values.as_u32(),
-            values.as_i32(),
-            values.as_u64(),
-            values.as_i64(),
-            values.as_f32(),
-            values.as_f64(),
-            values.as_str()
-        ])?;
+    /// Sets `PRAGMA locking_mode`.
+    ///
+    /// In [`LockingMode::Exclusive`] the file lock is kept after the first write,
+    /// blocking all other connections until the mode is set back to
+    /// [`LockingMode::Normal`] and the database is accessed again.
+    #[inline]
+    pub fn set_locking_mode(&self, mode: LockingMode) -> Result {
+        let conn = self.lock()?;
+        conn.pragma_update(None, "locking_mode", mode.as_str())?;
+        Ok(())
+    }
 
-        drop(stmt);
+    /// Returns `PRAGMA data_version`.
+    ///
+    /// The value changes whenever another connection commits to the database,
+    /// so comparing it against an earlier observation tells if anything changed.
+    #[inline]
+    pub fn data_version(&self) -> Result<i64> {
+        let conn = self.lock()?;
+        let version = conn.pragma_query_value(None, "data_version", |r| r.get(0))?;
+        Ok(version)
+    }
 
-        let mut inserter = Inserter { tx, source_id: id };
+    /// Runs `PRAGMA wal_checkpoint` to move the write-ahead log into the database.
+    ///
+    /// Returns the number of frames in the log and how many of them were
+    /// checkpointed, or `None` if the database isn't in WAL mode.
+    /// Fails with `SQLITE_BUSY` if a blocking `mode` couldn't
+    /// complete because of other connections.
+    #[inline]
+    pub fn wal_checkpoint(&self, mode: CheckpointMode) -> Result<Option<(i32, i32)>> {
+        let sql = format!("PRAGMA wal_checkpoint({})", mode.as_str());
 
-        data.provide_links(&mut inserter)?;
+        let conn = self.lock()?;
+        let (busy, log, checkpointed): (i32, i32, i32) =
+            conn.query_row(&sql, [], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+        if busy != 0 {
+            let e = rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY);
+            return Err(rusqlite::Error::SqliteFailure(e, None).into());
+        }
+        // SQLite reports -1 for both without a write-ahead log
+        if log < 0 {
+            return Ok(None);
+        }
+        Ok(Some((log, checkpointed)))
+    }
 
+    /// Drops all cached prepared statements.
+    ///
+    /// Dynamically built queries each occupy a slot of the cache,
+    /// this releases the memory held by them.
+    #[inline]
+    pub fn clear_statement_cache(&self) -> Result {
+        let conn = self.lock()?;
+        conn.flush_prepared_statement_cache();
         Ok(())
     }
 
+    /// Enables or disables logging of every executed statement.
+    ///
+    /// Statements are logged at `trace` level to [`SQL_LOG_TARGET`],
+    /// once when they start and once with their duration when they finish.
     #[inline]
-    #[must_use]
-    pub fn get(&self, id: ID) -> StoredData {
-        StoredData {
-            db: self.clone(),
-            id,
+    pub fn set_trace(&self, enabled: bool) -> Result {
+        let mut conn = self.lock()?;
+        if enabled {
+            conn.trace(Some(trace_statement));
+            conn.profile(Some(profile_statement));
+        } else {
+            conn.trace(None);
+            conn.profile(None);
         }
+        Ok(())
     }
 
+    /// Copies the whole database into a new in-memory database.
+    ///
+    /// The copy is a snapshot of the current state and doesn't see later
+    /// changes to this database, nor does this one see changes to the copy.
     #[inline]
-    fn is_ready(&self) -> bool {
-        self.schema_version()
-            .is_ok_and(|v| v == crate::schema_version!())
-        // const VALUES_COL_COUNT: &str = "SELECT COUNT(*) FROM pragma_table_info('values');";
-        // const LINKS_COL_COUNT: &str = "SELECT COUNT(*) FROM pragma_table_info('links');";
-        // const SCHEMA_VERSION: &str = "SELECT user_version FROM pragma_user_version();";
+    pub fn snapshot_to_memory(&self) -> Result<Self> {
+        let mut memory = Connection::open_in_memory()?;
+        {
+            let conn = self.lock()?;
+            let backup = Backup::new(&conn, &mut memory)?;
+            backup.run_to_completion(-1, Duration::ZERO, None)?;
+        }
+        let snapshot = Self::with_pragmas(memory)?;
+        Ok(Self {
+            conn: snapshot.conn,
+            ..self.clone()
+        })
+    }
 
-        // let conn = self.conn.lock().unwrap();
+    /// Creates an empty database at `path` with the same tables, indexes
+    /// and schema version as this one, without running any migrations.
+    pub fn clone_schema_to<P: AsRef<Path>>(&self, path: P) -> Result<Self> {
+        const SQL: &str = "SELECT sql FROM sqlite_master WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' ORDER BY rowid";
 
-        // let schema_version: i32 = conn
-        //     .query_row(SCHEMA_VERSION, [], |r| r.get(0))
-        //     .unwrap_or_default();
+        let (ddl, version) = {
+            let conn = self.lock()?;
+            let ddl = conn
+                .prepare(SQL)?
+                .query_map([], |r| r.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let version: i32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+            (ddl, version)
+        };
 
-        // if schema_version != crate::schema_version!() {
-        //     return false;
-        // }
+        let mut conn = Connection::open(path)?;
+        let tx = conn.transaction()?;
+        for sql in ddl {
+            tx.execute_batch(&sql)?;
+        }
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
 
-        // let values_col_count: u32 = conn
-        //     .query_row(VALUES_COL_COUNT, [], |r| r.get(0))
-        //     .unwrap_or_default();
+        let clone = Self::with_pragmas(conn)?;
+        Ok(Self {
+            conn: clone.conn,
+            ..self.clone()
+        })
+    }
 
-        // if values_col_count != 13 {
-        //     return false;
-        // }
-        // let links_col_count: u32 = conn
-        //     .query_row(LINKS_COL_COUNT, [], |r| r.get(0))
-        //     .unwrap_or_default();
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_pragmas(Connection::open(path)?)
+    }
 
-        // if links_col_count != 3 {
-        //     return false;
-        // }
+    #[inline]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_pragmas(Connection::open_in_memory()?)
+    }
 
-        // true
+    /// Opens the database at `path` and brings its schema up to date,
+    /// see [`Database::open_in_memory_initialized`].
+    #[inline]
+    pub fn open_initialized<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open(path)?.initialized()
     }
-}
 
-impl From<Connection> for Database {
+    /// Opens an in-memory database ready to store data.
+    ///
+    /// Runs [`Database::migrate`] with the `migrations` feature
+    /// and [`Database::init`] without it.
     #[inline]
-    fn from(conn: Connection) -> Self {
-        Self::new(conn)
+    pub fn open_in_memory_initialized() -> Result<Self> {
+        Self::open_in_memory()?.initialized()
     }
-}
 
-impl Data for Database {
+    /// Opens the existing database at `path` without ever writing to it.
+    ///
+    /// Fails if the file doesn't exist. Storing, [`Database::init`] and
+    /// [`Database::migrate`] fail with [`Error::ReadOnly`].
     #[inline]
-    fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
-        let conn = self.conn.lock().unwrap();
-        if let Some(path) = conn.path() {
-            links.push_link(("path", path.to_owned()))?;
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_pragmas(Connection::open_with_flags(path, READ_ONLY_FLAGS)?)
+    }
+
+    /// Opens the database at `path` with all settings of `config` applied.
+    ///
+    /// `DbConfig::default()` opens it like [`Database::open`] does.
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: DbConfig) -> Result<Self> {
+        let conn = if config.read_only {
+            Connection::open_with_flags(path, READ_ONLY_FLAGS)?
+        } else {
+            Connection::open(path)?
+        };
+        conn.pragma_update(None, "foreign_keys", config.foreign_keys)?;
+        conn.busy_timeout(config.busy_timeout)?;
+        if let Some(mode) = config.journal_mode {
+            conn.pragma_update(None, "journal_mode", mode.as_str())?;
+        }
+        if let Some(size) = config.cache_size {
+            conn.pragma_update(None, "cache_size", size.as_pragma())?;
         }
 
-        links.push_link(("last_insert_rowid", conn.last_insert_rowid()))?;
-        links.push_link(("last_changes", conn.changes()))?;
-        links.push_link(("autocommit", conn.is_autocommit()))?;
-        links.push_link(("busy", conn.is_busy()))?;
-        drop(conn);
+        let mut db = Self::new(conn)
+            .with_schema(config.schema)
+            .with_id_strategy(config.id_strategy)
+            .with_transaction_behavior(config.transaction_behavior);
+        if let Some(seed) = config.seed {
+            db = db.with_random_seed(seed);
+        }
+        Ok(db)
+    }
 
-        self.query_links(links, &Default::default())
+    /// The file of the database, `None` for in-memory and temporary databases.
+    #[inline]
+    pub fn path(&self) -> Result<Option<PathBuf>> {
+        let conn = self.lock()?;
+        // Depending on the SQLite version an empty path is returned instead of none
+        Ok(conn.path().filter(|p| !p.is_empty()).map(PathBuf::from))
     }
 
     #[inline]
-    fn query_links(&self, links: &mut dyn Links, query: &Query) -> Result<(), LinkError> {
-        let context = QueryContext {
-            table: "values".into(),
-            key_col: "uuid".into(),
-            target_col: "uuid".into(),
-        };
-        let mut sql = SQLBuilder::new_conjunct(context);
-        // Ensure column #0 is the ID
-        sql.select("`values`.`uuid`");
-        query.build_sql(&mut sql)?;
+    pub fn is_memory(&self) -> Result<bool> {
+        Ok(self.path()?.is_none())
+    }
 
-        build_links(self, &sql, links, |r| {
-            let id = r.get::<_, SqlID>(0)?;
-            Ok(self.get(id.into()))
-        })?;
+    #[inline]
+    pub fn is_read_only(&self) -> Result<bool> {
+        let conn = self.lock()?;
+        Ok(conn.is_readonly(DatabaseName::Main)?)
+    }
 
+    #[inline]
+    fn check_read_only(&self) -> Result {
+        if self.is_read_only()? {
+            return Err(Error::ReadOnly);
+        }
         Ok(())
     }
-}
-
-struct Inserter<'tx> {
-    tx: &'tx rusqlite::Transaction<'tx>,
-    source_id: SqlID,
-}
 
-impl Links for Inserter<'_> {
+    /// Fails unless data can be stored with the schema of this crate.
     #[inline]
-    fn push_unkeyed(&mut self, target: BoxedData) -> LResult {
-        let target = target.into_unique_random();
-        Database::store_inner(self.tx, &target)?;
-
-        let mut stmt = self
-            .tx
-            .prepare_cached(INSERT_LINK_UNKEYED)
-            .map_err(LinkError::other)?;
-        stmt.execute([self.source_id, target.id().into()])
-            .map_err(LinkError::other)?;
+    pub(crate) fn check_writable(&self) -> Result {
+        let conn = self.lock()?;
+        check_writable(&conn)
+    }
 
-        CONTINUE
+    #[inline]
+    fn initialized(self) -> Result<Self> {
+        // A newer schema can still be read
+        if self.compatibility()? == Compatibility::Newer {
+            return Ok(self);
+        }
+        #[cfg(feature = "migrations")]
+        self.migrate()?;
+        #[cfg(not(feature = "migrations"))]
+        self.init()?;
+        Ok(self)
     }
 
     #[inline]
-    fn push_keyed(&mut self, target: BoxedData, key: BoxedData) -> LResult {
-        let target = target.into_unique_random();
-        Database::store_inner(self.tx, &target)?;
+    pub fn store<D: Data + Unique>(&self, data: &D) -> Result<StoredData> {
+        self.check_writable()?;
+        let mut conn = self.lock()?;
+        let tx = conn.transaction_with_behavior(self.tx_behavior)?;
+        self.store_inner(&tx, data)?;
+        tx.commit()?;
+        Ok(StoredData {
+            db: self.clone(),
+            id: data.id(),
+        })
+    }
 
-        let key = key.into_unique_random();
-        Database::store_inner(self.tx, &key)?;
+    /// Stores `data` in `tx` without committing, so it only persists
+    /// together with the rest of the transaction.
+    ///
+    /// `tx` has to belong to this database, like the one passed by [`Database::transaction_with`].
+    #[inline]
+    pub fn store_in<D: Data + Unique>(&self, tx: &Transaction, data: &D) -> Result<StoredData> {
+        check_writable(tx)?;
+        self.store_inner(tx, data)?;
+        Ok(self.get(data.id()))
+    }
 
-        let mut stmt = self
-            .tx
-            .prepare_cached(INSERT_LINK_KEYED)
-            .map_err(LinkError::other)?;
-        stmt.execute([self.source_id, target.id().into(), key.id().into()])
-            .map_err(LinkError::other)?;
+    /// Runs `f` in a transaction, committed if `f` returns `Ok`
+    /// and rolled back otherwise.
+    #[inline]
+    pub fn transaction_with<R>(&self, f: impl FnOnce(&Transaction) -> Result<R>) -> Result<R> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction_with_behavior(self.tx_behavior)?;
+        let res = f(&tx)?;
+        tx.commit()?;
+        Ok(res)
+    }
 
-        CONTINUE
+    /// Starts a savepoint in `tx`, which can be rolled back
+    /// without affecting the rest of the transaction.
+    #[inline]
+    pub fn transaction_savepoint<'tx>(&'tx self, tx: &'tx Transaction) -> Result<Savepoint<'tx>> {
+        let n = NEXT_SAVEPOINT.fetch_add(1, AtomicOrdering::Relaxed);
+        let name = format!("datalink_savepoint_{n}");
+        tx.execute_batch(&format!("SAVEPOINT `{name}`"))?;
+        Ok(Savepoint {
+            db: self,
+            tx,
+            name,
+            done: false,
+        })
     }
 
+    /// Renames every occurrence of `from` to `to`, both in the values and all links.
+    ///
+    /// If `to` already exists, both are merged: the links of `from` are added to
+    /// the ones of `to` and values only missing from `to` are taken from `from`.
+    /// Returns if such a merge happened.
     #[inline]
-    fn push(&mut self, target: BoxedData, key: Option<BoxedData>) -> LResult {
-        if let Some(key) = key {
-            self.push_keyed(target, key)
-        } else {
-            self.push_unkeyed(target)
+    pub fn rename_id(&self, from: ID, to: ID) -> Result<bool> {
+        const EXISTS: &str = "SELECT EXISTS (SELECT 1 FROM `values` WHERE `uuid` = ?1) OR EXISTS (SELECT 1 FROM `links` WHERE `source_uuid` = ?1)";
+        const HAS_VALUES: &str = "SELECT EXISTS (SELECT 1 FROM `values` WHERE `uuid` = ?1)";
+        const RENAME_VALUES: &str = "UPDATE `values` SET `uuid` = ?2 WHERE `uuid` = ?1";
+        const DELETE_VALUES: &str = "DELETE FROM `values` WHERE `uuid` = ?1";
+        const RENAME_LINKS: &str = "UPDATE `links` SET `source_uuid` = ?2 WHERE `source_uuid` = ?1;
+UPDATE `links` SET `key_uuid` = ?2 WHERE `key_uuid` = ?1;
+UPDATE `links` SET `target_uuid` = ?2 WHERE `target_uuid` = ?1;";
+
+        if from == to {
+            return Ok(false);
         }
+        let ids = [SqlID::from(from), SqlID::from(to)];
+        let schema = &self.schema;
+
+        self.check_writable()?;
+        self.transaction_with(|tx| {
+            let merge: bool = tx.query_row(&schema.render(EXISTS), [ids[1]], |r| r.get(0))?;
+            // `to` may only be the source of links without a row of values
+            let has_values: bool =
+                tx.query_row(&schema.render(HAS_VALUES), [ids[1]], |r| r.get(0))?;
+            if has_values {
+                let merged = PrimitiveType::ALL
+                    .iter()
+                    .map(|ty| {
+                        let col = ty.column();
+                        format!("`{col}` = COALESCE(`{col}`, (SELECT `{col}` FROM `values` WHERE `uuid` = ?1))")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let merge_values = format!("UPDATE `values` SET {merged} WHERE `uuid` = ?2");
+                tx.execute(&schema.render(&merge_values), ids)?;
+                tx.execute(&schema.render(DELETE_VALUES), [ids[0]])?;
+            } else {
+                tx.execute(&schema.render(RENAME_VALUES), ids)?;
+            }
+            for sql in schema.render(RENAME_LINKS).split_terminator(';') {
+                tx.execute(sql, ids)?;
+            }
+            Ok(merge)
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use datalink::data::DataExt;
+    /// Hashes the values and links of all data, ignoring row order and timestamps.
+    ///
+    /// Databases with the same logical content have the same hash. The hash is
+    /// 64 bit FNV-1a over a fixed little-endian encoding of all cells, so it
+    /// doesn't depend on the platform or toolchain.
+    pub fn content_hash(&self) -> Result<u64> {
+        const LINKS: &str = "SELECT `source_uuid`, `key_uuid`, `target_uuid` FROM `links` ORDER BY `source_uuid`, `key_uuid`, `target_uuid`";
+        let values = format!(
+            "SELECT `uuid`, {} FROM `values` ORDER BY `uuid`",
+            primitive::all_columns()
+        );
 
-    fn test_db() -> Database {
-        let db = Database::open_in_memory().unwrap();
-        db.init().unwrap();
-        db
+        let mut hasher = Fnv1a::default();
+        let conn = self.lock()?;
+        let value_columns = PrimitiveType::ALL.len() + 1;
+        for (sql, columns) in [(values.as_str(), value_columns), (LINKS, 3)] {
+            let mut stmt = conn.prepare_cached(&self.schema.render(sql))?;
+            let mut rows = stmt.query([])?;
+            let mut count = 0u64;
+            while let Some(row) = rows.next()? {
+                count += 1;
+                for idx in 0..columns {
+                    hash_value(row.get_ref(idx)?, &mut hasher);
+                }
+            }
+            // Separates both tables
+            hasher.write(&count.to_le_bytes());
+        }
+        Ok(hasher.finish())
     }
 
-    #[test]
-    fn empty() {
-        let db = test_db();
-
-        // No data without a key
-        let list = db.as_list().unwrap();
-        assert_eq!(list.len(), 0);
+    /// Deletes links whose source, key or target has no values row.
+    ///
+    /// Stored data always has a values row, so this only removes links left
+    /// behind by deletes or external edits. Returns the number of removed links.
+    #[inline]
+    pub fn repair_orphans(&self) -> Result<usize> {
+        const SQL: &str = "DELETE FROM `links`
+WHERE NOT EXISTS (SELECT 1 FROM `values` WHERE `values`.`uuid` = `links`.`source_uuid`)
+OR NOT EXISTS (SELECT 1 FROM `values` WHERE `values`.`uuid` = `links`.`target_uuid`)
+OR (`links`.`key_uuid` IS NOT NULL AND NOT EXISTS (SELECT 1 FROM `values` WHERE `values`.`uuid` = `links`.`key_uuid`))";
 
-        let items = db.as_items().unwrap();
-        dbg!(items);
+        self.check_writable()?;
+        self.transaction_with(|tx| Ok(tx.execute(&self.schema.render(SQL), [])?))
     }
 
-    #[test]
-    fn in_out() {
-        let db = test_db();
+    /// Deletes all values and links not reachable from `roots` by following links
+    /// to their targets and keys, returning the number of deleted rows.
+    ///
+    /// Fails with [`Error::NoRoots`] instead of deleting everything if `roots` is empty.
+    pub fn prune_unreferenced(&self, roots: &[ID]) -> Result<usize> {
+        const CREATE: &str = "CREATE TEMP TABLE `prune_roots` (`uuid` BLOB PRIMARY KEY);
+CREATE TEMP TABLE `prune_reachable` (`uuid` BLOB PRIMARY KEY);";
+        const INSERT_ROOT: &str = "INSERT OR IGNORE INTO `prune_roots` (`uuid`) VALUES (?)";
+        const MARK: &str = "INSERT INTO `prune_reachable`
+WITH RECURSIVE `edges` (`source`, `target`) AS (
+    SELECT `source_uuid`, `target_uuid` FROM `links`
+    UNION ALL
+    SELECT `source_uuid`, `key_uuid` FROM `links` WHERE `key_uuid` IS NOT NULL
+), `reachable` (`uuid`) AS (
+    SELECT `uuid` FROM `prune_roots`
+    UNION
+    SELECT `edges`.`target` FROM `edges` JOIN `reachable` ON `edges`.`source` = `reachable`.`uuid`
+)
+SELECT `uuid` FROM `reachable`";
+        const SWEEP_LINKS: &str =
+            "DELETE FROM `links` WHERE `source_uuid` NOT IN (SELECT `uuid` FROM `prune_reachable`)";
+        const SWEEP_VALUES: &str =
+            "DELETE FROM `values` WHERE `uuid` NOT IN (SELECT `uuid` FROM `prune_reachable`)";
+        const DROP: &str = "DROP TABLE `prune_roots`;
+DROP TABLE `prune_reachable`;";
 
-        let data = true.into_unique_random();
-        let stored = db.store(&data).unwrap();
+        if roots.is_empty() {
+            return Err(Error::NoRoots);
+        }
+        self.check_writable()?;
 
-        assert_eq!(true, stored.as_bool().unwrap());
+        self.transaction_with(|tx| {
+            tx.execute_batch(&self.schema.render(CREATE))?;
+            let mut stmt = tx.prepare(&self.schema.render(INSERT_ROOT))?;
+            for root in roots {
+                stmt.execute([SqlID::from(*root)])?;
+            }
+            drop(stmt);
+
+            tx.execute(&self.schema.render(MARK), [])?;
+            let links = tx.execute(&self.schema.render(SWEEP_LINKS), [])?;
+            let values = tx.execute(&self.schema.render(SWEEP_VALUES), [])?;
+            tx.execute_batch(&self.schema.render(DROP))?;
+            log::debug!("Pruned {values} values and {links} links");
+            Ok(values + links)
+        })
     }
 
-    #[test]
-    fn in_out_vec() {
-        let db = test_db();
+    /// Copies all data of the database at `path` into this one,
+    /// returning the number of copied values and links.
+    ///
+    /// Values with an id already present here are skipped, as are duplicate links.
+    /// Both databases must be at the current schema version and use the same [`Schema`],
+    /// with tables of the same columns.
+    pub fn import_from_file<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        // `ATTACH` would create an empty database instead
+        if !path.is_file() {
+            return Err(Error::NotFound(path.to_path_buf()));
+        }
+        let path = path.to_string_lossy();
+
+        let mut conn = self.lock()?;
+        conn.execute("ATTACH DATABASE ? AS `other`", [path])?;
+        let res = self.import_attached(&mut conn);
+        conn.execute_batch("DETACH DATABASE `other`;")?;
+        res
+    }
+
+    fn import_attached(&self, conn: &mut Connection) -> Result<usize> {
+        const VALUES: &str = "INSERT OR IGNORE INTO `values` SELECT * FROM `other`.`values`";
+        const LINKS: &str = "INSERT INTO `links`
+SELECT * FROM `other`.`links` AS `l`
+WHERE NOT EXISTS (
+    SELECT 1 FROM `links`
+    WHERE `links`.`source_uuid` = `l`.`source_uuid`
+    AND `links`.`key_uuid` IS `l`.`key_uuid`
+    AND `links`.`target_uuid` = `l`.`target_uuid`
+)";
+
+        let version: i32 =
+            conn.pragma_query_value(Some(DatabaseName::Attached("other")), "user_version", |r| {
+                r.get(0)
+            })?;
+        if version != crate::schema_version!() {
+            return Err(Error::SchemaMismatch(crate::schema_version!()));
+        }
+        // Both tables are copied by position
+        for table in [&self.schema.values, &self.schema.links] {
+            if table_columns(conn, "main", table)? != table_columns(conn, "other", table)? {
+                return Err(Error::SchemaMismatch(crate::schema_version!()));
+            }
+        }
+
+        let tx = conn.transaction_with_behavior(self.tx_behavior)?;
+        let values = tx.execute(&self.schema.render(VALUES), [])?;
+        let links = tx.execute(&self.schema.render(LINKS), [])?;
+        tx.commit()?;
+        log::debug!("Imported {values} values and {links} links");
+        Ok(values + links)
+    }
+
+    /// Runs a single arbitrary statement and returns the number of changed rows.
+    ///
+    /// Identifiers are rendered like the built-in statements, see [`Schema::render`].
+    /// Input with more than one statement is refused, use
+    /// [`Database::transaction_with`] and `execute_batch` for scripts.
+    #[inline]
+    pub fn execute_raw(&self, sql: &str, params: impl Params) -> Result<usize> {
+        let sql = self.schema.render(sql);
+        let conn = self.lock()?;
+        // `Connection::execute` only refuses trailing statements with rusqlite's `extra_check`
+        let mut batch = Batch::new(&conn, &sql);
+        let Some(mut stmt) = batch.next()? else {
+            return Ok(0);
+        };
+        if batch.next()?.is_some() {
+            return Err(rusqlite::Error::MultipleStatement.into());
+        }
+        let changed = stmt.execute(params)?;
+        Ok(changed)
+    }
+
+    /// Runs an arbitrary `SELECT` and maps every row with `f`.
+    ///
+    /// Identifiers are rendered like in [`Database::execute_raw`].
+    #[inline]
+    pub fn query_map_to_vec<T>(
+        &self,
+        sql: &str,
+        params: impl Params,
+        f: impl FnMut(&Row) -> rusqlite::Result<T>,
+    ) -> Result<Vec<T>> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare_cached(&self.schema.render(sql))?;
+        let rows = stmt
+            .query_map(params, f)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// Runs `f` to store large amounts of data at once.
+    ///
+    /// All data is stored in one transaction with `PRAGMA synchronous = OFF`,
+    /// and the lookup indexes are only rebuilt at the end.
+    /// If `f` fails nothing is stored and the indexes are kept.
+    #[inline]
+    pub fn bulk_load(&self, f: impl FnOnce(&mut BulkLoader) -> Result) -> Result {
+        self.check_writable()?;
+        let mut conn = self.lock()?;
+        let synchronous: i32 = conn.pragma_query_value(None, "synchronous", |r| r.get(0))?;
+        conn.pragma_update(None, "synchronous", "OFF")?;
+
+        let res = (|| -> Result {
+            let tx = conn.transaction_with_behavior(self.tx_behavior)?;
+            tx.execute_batch(&self.schema.render(BULK_DROP_INDEXES))?;
+            f(&mut BulkLoader { db: self, tx: &tx })?;
+            tx.execute_batch(&self.schema.render(BULK_CREATE_INDEXES))?;
+            tx.commit()?;
+            Ok(())
+        })();
+
+        conn.pragma_update(None, "synchronous", synchronous)?;
+        res
+    }
+
+    /// Runs `f` with a [`DbLinkSink`] storing every link pushed to it as link
+    /// of `source`, all in one transaction.
+    ///
+    /// A values row without values is created for `source` if it has none.
+    #[inline]
+    pub fn link_sink<R>(
+        &self,
+        source: ID,
+        f: impl FnOnce(&mut DbLinkSink) -> Result<R>,
+    ) -> Result<R> {
+        const INSERT_SOURCE: &str = "INSERT OR IGNORE INTO `values` (`uuid`) VALUES (?)";
+
+        self.check_writable()?;
+        let source_id = SqlID::from(source);
+        self.transaction_with(|tx| {
+            tx.execute(&self.schema.render(INSERT_SOURCE), [source_id])?;
+            f(&mut DbLinkSink(Inserter {
+                db: self,
+                tx,
+                source_id,
+            }))
+        })
+    }
+
+    /// Stores every item of `data`, stopping at the first error.
+    #[inline]
+    pub fn store_all<D: Data + Unique>(&self, data: impl IntoIterator<Item = D>) -> Result {
+        for d in data {
+            self.store(&d)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the stored data for `data`, only storing it if its id isn't
+    /// present yet.
+    #[inline]
+    pub fn get_or_store<D: Data + Unique>(&self, data: &D) -> Result<StoredData> {
+        let id = data.id();
+        if self.exists(id)? {
+            return Ok(self.get(id));
+        }
+        self.store(data)
+    }
+
+    /// Checks if a values row or links are stored for `id`.
+    #[inline]
+    pub fn exists(&self, id: ID) -> Result<bool> {
+        const SQL: &str = "SELECT EXISTS (SELECT 1 FROM `values` WHERE `uuid` = ?1) OR EXISTS (SELECT 1 FROM `links` WHERE `source_uuid` = ?1)";
+
+        let conn = self.lock()?;
+        let exists = conn
+            .prepare_cached(&self.schema.render(SQL))?
+            .query_row([SqlID::from(id)], |r| r.get(0))?;
+        Ok(exists)
+    }
+
+    /// Returns the key of every link from `source` to `target`, `None` for unkeyed ones,
+    /// in insertion order.
+    #[inline]
+    pub fn links_between(
+        &self,
+        source: impl Into<ID>,
+        target: impl Into<ID>,
+    ) -> Result<Vec<Option<ID>>> {
+        const SQL: &str = "SELECT `key_uuid` FROM `links` WHERE `source_uuid` = ? AND `target_uuid` = ? ORDER BY `rowid`";
+
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare_cached(&self.schema.render(SQL))?;
+        let keys = stmt
+            .query_map(
+                [SqlID::from(source.into()), SqlID::from(target.into())],
+                |r| Ok(r.get::<_, Option<SqlID>>(0)?.map(ID::from)),
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(keys)
+    }
+
+    #[inline]
+    fn store_inner<D: Data + Unique>(&self, tx: &Transaction, data: &D) -> Result<()> {
+        self.store_as(tx, data.id(), data)
+    }
+
+    /// Stores `data` under `id`, regardless of its own id.
+    pub(crate) fn store_as<D: Data>(&self, tx: &Transaction, id: ID, data: &D) -> Result<()> {
+        use datalink::data::DataExt;
+        use PrimitiveType as T;
+
+        let mut stmt = tx.prepare_cached(&self.schema.render(insert_values()))?;
+
+        let id = id.into();
+        let values = data.all_values();
+        let primitives = PrimitiveType::ALL.map(|ty| match ty {
+            T::Bool => values.as_bool().map(Primitive::from),
+            T::U8 => values.as_u8().map(Primitive::from),
+            T::I8 => values.as_i8().map(Primitive::from),
+            T::U16 => values.as_u16().map(Primitive::from),
+            T::I16 => values.as_i16().map(Primitive::from),
+            T::U32 => values.as_u32().map(Primitive::from),
+            T::I32 => values.as_i32().map(Primitive::from),
+            T::U64 => values.as_u64().map(Primitive::from),
+            T::I64 => values.as_i64().map(Primitive::from),
+            T::F32 => values.as_f32().map(Primitive::from),
+            T::F64 => values.as_f64().map(Primitive::from),
+            T::Str => values.as_str().map(|s| Primitive::Str(s.into())),
+        });
+
+        stmt.raw_bind_parameter(1, id)?;
+        for (idx, primitive) in primitives.iter().enumerate() {
+            stmt.raw_bind_parameter(idx + 2, primitive)?;
+        }
+        stmt.raw_execute()?;
+
+        drop(stmt);
+
+        let mut inserter = Inserter {
+            db: self,
+            tx,
+            source_id: id,
+        };
+
+        data.provide_links(&mut inserter)?;
+
+        Ok(())
+    }
+
+    /// Generates the SQL for `query` over all data, see [`CompiledQuery`].
+    #[inline]
+    pub fn prepare_query(&self, query: &Query) -> Result<CompiledQuery> {
+        let mut sql = SQLBuilder::new_conjunct(QueryContext::values());
+        // Ensure column #0 is the ID
+        sql.select("`values`.`uuid`");
+        query.build_sql(&mut sql)?;
+
+        Ok(CompiledQuery {
+            db: self.clone(),
+            sql,
+        })
+    }
+
+    /// Returns the ids of all data matching `query`.
+    ///
+    /// Every stored data is matched once, as both key and target of a link.
+    #[inline]
+    pub fn query_ids_filtered(&self, query: &Query) -> Result<Vec<ID>> {
+        self.prepare_query(query)?.ids()
+    }
+
+    /// Returns the data matching `query` with their values,
+    /// see [`CompiledQuery::with_values`].
+    #[inline]
+    pub fn query_with_values(&self, query: &Query) -> Result<Vec<(StoredData, Vec<Primitive>)>> {
+        self.prepare_query(query)?.with_values()
+    }
+
+    /// Returns the number of data matching `query`, see [`CompiledQuery::count`].
+    #[inline]
+    pub fn count_query(&self, query: &Query) -> Result<u64> {
+        self.prepare_query(query)?.count()
+    }
+
+    /// Lazily iterates every non-NULL value of every data, see [`ValuesIter`].
+    #[inline]
+    #[must_use]
+    pub fn values_iter(&self) -> ValuesIter {
+        ValuesIter {
+            db: self.clone(),
+            last_rowid: 0,
+            buffer: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get(&self, id: ID) -> StoredData {
+        StoredData {
+            db: self.clone(),
+            id,
+        }
+    }
+
+    /// Locks the connection for the current thread.
+    ///
+    /// Returns [`Error::Reentrant`] instead of deadlocking if this thread
+    /// already holds the lock, e.g. when data being stored reads from
+    /// the same database.
+    #[inline]
+    pub(crate) fn lock(&self) -> Result<ConnGuard<'_>> {
+        let key = Arc::as_ptr(&self.conn) as usize;
+        if LOCKED.with_borrow(|locked| locked.contains(&key)) {
+            return Err(Error::Reentrant);
+        }
+        // A panicking thread rolls back its open transaction while unwinding,
+        // so the connection stays usable
+        let conn = self.conn.lock().unwrap_or_else(PoisonError::into_inner);
+        LOCKED.with_borrow_mut(|locked| locked.push(key));
+        Ok(ConnGuard { conn, key })
+    }
+
+    #[inline]
+    fn is_ready(&self) -> bool {
+        self.schema_version()
+            .is_ok_and(|v| v == crate::schema_version!())
+        // const VALUES_COL_COUNT: &str = "SELECT COUNT(*) FROM pragma_table_info('values');";
+        // const LINKS_COL_COUNT: &str = "SELECT COUNT(*) FROM pragma_table_info('links');";
+        // const SCHEMA_VERSION: &str = "SELECT user_version FROM pragma_user_version();";
+
+        // let conn = self.conn.lock().unwrap();
+
+        // let schema_version: i32 = conn
+        //     .query_row(SCHEMA_VERSION, [], |r| r.get(0))
+        //     .unwrap_or_default();
+
+        // if schema_version != crate::schema_version!() {
+        //     return false;
+        // }
+
+        // let values_col_count: u32 = conn
+        //     .query_row(VALUES_COL_COUNT, [], |r| r.get(0))
+        //     .unwrap_or_default();
+
+        // if values_col_count != 13 {
+        //     return false;
+        // }
+        // let links_col_count: u32 = conn
+        //     .query_row(LINKS_COL_COUNT, [], |r| r.get(0))
+        //     .unwrap_or_default();
+
+        // if links_col_count != 3 {
+        //     return false;
+        // }
+
+        // true
+    }
+}
+
+/// Fails unless data can be stored in `conn` with the schema of this crate.
+fn check_writable(conn: &Connection) -> Result {
+    if conn.is_readonly(DatabaseName::Main)? {
+        return Err(Error::ReadOnly);
+    }
+    let version: i32 = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    match Compatibility::of(version) {
+        Compatibility::Exact => Ok(()),
+        Compatibility::Older => Err(Error::NotInitialized),
+        Compatibility::Newer => Err(Error::SchemaTooNew),
+    }
+}
+
+/// Guesses the schema version from the columns of the `values` table.
+///
+/// Returns `None` for a shape no version has, like a half applied migration.
+pub(crate) fn detect_version(conn: &Connection, schema: &Schema) -> Result<Option<i32>> {
+    const SQL: &str = "SELECT `name` FROM pragma_table_info(?)";
+
+    let mut stmt = conn.prepare(SQL)?;
+    let columns = stmt
+        .query_map([&schema.values], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let has = |col: &str| columns.iter().any(|c| c == col);
+
+    let version = match (has("id"), has(&schema.uuid), has("created_at")) {
+        _ if columns.is_empty() => Some(0),
+        (true, false, false) => Some(1),
+        (false, true, false) => Some(2),
+        (false, true, true) => Some(3),
+        _ => None,
+    };
+    Ok(version)
+}
+
+/// Names of the columns of `table` in the attached database `db`, in order.
+fn table_columns(conn: &Connection, db: &str, table: &str) -> Result<Vec<String>> {
+    const SQL: &str = "SELECT `name` FROM pragma_table_info(?, ?) ORDER BY `cid`";
+
+    let mut stmt = conn.prepare(SQL)?;
+    let columns = stmt
+        .query_map([table, db], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(columns)
+}
+
+/// State of an opened file, see [`Database::detect_schema`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaState {
+    /// No tables at all, ready for [`Database::init`]
+    Empty,
+    /// An older schema version, which can be migrated
+    Legacy(i32),
+    /// Matches this crate
+    Current,
+    /// Has datalink tables, but their shape doesn't match the recorded version
+    Unknown,
+    /// Contains tables, but not the ones of datalink
+    NotDatalink,
+}
+
+/// Schema version of a database relative to this crate, see [`Database::compatibility`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Uninitialized or in need of migrations
+    Older,
+    /// Matches this crate
+    Exact,
+    /// Written by a newer version of this crate, only readable
+    Newer,
+}
+
+impl Compatibility {
+    #[inline]
+    fn of(version: i32) -> Self {
+        match version.cmp(&crate::schema_version!()) {
+            Ordering::Less => Self::Older,
+            Ordering::Equal => Self::Exact,
+            Ordering::Greater => Self::Newer,
+        }
+    }
+}
+
+/// Mode of [`Database::set_locking_mode`], see the
+/// [SQLite documentation](https://www.sqlite.org/pragma.html#pragma_locking_mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockingMode {
+    /// Release the file lock after each transaction
+    #[default]
+    Normal,
+    /// Keep the file lock once acquired, for single process access
+    Exclusive,
+}
+
+impl LockingMode {
+    #[inline]
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "NORMAL",
+            Self::Exclusive => "EXCLUSIVE",
+        }
+    }
+}
+
+/// Mode of [`Database::wal_checkpoint`], see the
+/// [SQLite documentation](https://www.sqlite.org/pragma.html#pragma_wal_checkpoint).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CheckpointMode {
+    /// Checkpoint as many frames as possible without waiting
+    #[default]
+    Passive,
+    /// Wait for writers, then checkpoint all frames
+    Full,
+    /// Like [`CheckpointMode::Full`], also waiting for readers so the log restarts
+    Restart,
+    /// Like [`CheckpointMode::Restart`], also truncating the log file
+    Truncate,
+}
+
+impl CheckpointMode {
+    #[inline]
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Passive => "PASSIVE",
+            Self::Full => "FULL",
+            Self::Restart => "RESTART",
+            Self::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// Size of the page cache, see [`Database::set_cache_size`].
+///
+/// `PRAGMA cache_size` takes a number of pages when positive and KiB when
+/// negative, the variants spell out which one is meant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheSize {
+    Pages(u32),
+    KiB(u32),
+}
+
+impl CacheSize {
+    /// A cache of `mib` MiB, capped at `u32::MAX` KiB.
+    #[inline]
+    #[must_use]
+    pub const fn mib(mib: u32) -> Self {
+        Self::KiB(mib.saturating_mul(1024))
+    }
+
+    /// The value of `PRAGMA cache_size`.
+    #[inline]
+    #[must_use]
+    pub const fn as_pragma(self) -> i64 {
+        match self {
+            Self::Pages(pages) => pages as i64,
+            Self::KiB(kib) => -(kib as i64),
+        }
+    }
+}
+
+/// Value of `PRAGMA journal_mode`, see [`DbConfig::journal_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    #[inline]
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Delete => "DELETE",
+            Self::Truncate => "TRUNCATE",
+            Self::Persist => "PERSIST",
+            Self::Memory => "MEMORY",
+            Self::Wal => "WAL",
+            Self::Off => "OFF",
+        }
+    }
+}
+
+/// Settings applied by [`Database::open_with_config`].
+///
+/// The default matches [`Database::open`], options left `None` keep
+/// SQLite's own defaults.
+#[derive(Clone)]
+pub struct DbConfig {
+    pub read_only: bool,
+    pub journal_mode: Option<JournalMode>,
+    pub busy_timeout: Duration,
+    pub cache_size: Option<CacheSize>,
+    pub foreign_keys: bool,
+    pub schema: Schema,
+    pub id_strategy: IdStrategy,
+    pub transaction_behavior: TransactionBehavior,
+    /// See [`Database::with_random_seed`]
+    pub seed: Option<u64>,
+}
+
+impl Default for DbConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            journal_mode: None,
+            busy_timeout: BUSY_TIMEOUT,
+            cache_size: None,
+            foreign_keys: true,
+            schema: Schema::default(),
+            id_strategy: IdStrategy::default(),
+            transaction_behavior: TransactionBehavior::Deferred,
+            seed: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for DbConfig {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DbConfig")
+            .field("read_only", &self.read_only)
+            .field("journal_mode", &self.journal_mode)
+            .field("busy_timeout", &self.busy_timeout)
+            .field("cache_size", &self.cache_size)
+            .field("foreign_keys", &self.foreign_keys)
+            .field("schema", &self.schema)
+            .field("id_strategy", &self.id_strategy)
+            .field(
+                "transaction_behavior",
+                &behavior_name(self.transaction_behavior),
+            )
+            .field("seed", &self.seed)
+            .finish()
+    }
+}
+
+thread_local! {
+    /// Connections locked by the current thread
+    static LOCKED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The locked connection of a [`Database`], see [`Database::lock`].
+pub(crate) struct ConnGuard<'db> {
+    conn: MutexGuard<'db, Connection>,
+    key: usize,
+}
+
+impl Deref for ConnGuard<'_> {
+    type Target = Connection;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl DerefMut for ConnGuard<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+impl Drop for ConnGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        LOCKED.with_borrow_mut(|locked| {
+            if let Some(i) = locked.iter().rposition(|&key| key == self.key) {
+                locked.swap_remove(i);
+            }
+        });
+    }
+}
+
+fn trace_statement(sql: &str) {
+    log::trace!(target: SQL_LOG_TARGET, "{sql}");
+}
+
+fn profile_statement(sql: &str, duration: Duration) {
+    log::trace!(target: SQL_LOG_TARGET, "{sql} ({duration:?})");
+}
+
+impl std::fmt::Debug for Database {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Database")
+            .field("conn", &self.conn)
+            .field("schema", &self.schema)
+            .field("id_strategy", &self.id_strategy)
+            .field("seeded", &self.seed.is_some())
+            .field("tx_behavior", &behavior_name(self.tx_behavior))
+            .finish()
+    }
+}
+
+/// [`TransactionBehavior`] doesn't implement `Debug`
+const fn behavior_name(behavior: TransactionBehavior) -> &'static str {
+    match behavior {
+        TransactionBehavior::Deferred => "Deferred",
+        TransactionBehavior::Immediate => "Immediate",
+        TransactionBehavior::Exclusive => "Exclusive",
+        _ => "Unknown",
+    }
+}
+
+impl From<Connection> for Database {
+    #[inline]
+    fn from(conn: Connection) -> Self {
+        Self::new(conn)
+    }
+}
+
+/// Collects into a new initialized in-memory database.
+///
+/// # Panics
+///
+/// If opening the database or storing any item fails,
+/// use [`Database::store_all`] to handle errors.
+impl<D: Data + Unique> FromIterator<D> for Database {
+    #[inline]
+    fn from_iter<T: IntoIterator<Item = D>>(iter: T) -> Self {
+        let mut db = Self::open_in_memory().expect("Failed to open in-memory database");
+        db.init().expect("Failed to initialize database");
+        db.extend(iter);
+        db
+    }
+}
+
+/// # Panics
+///
+/// If storing any item fails, use [`Database::store_all`] to handle errors.
+impl<D: Data + Unique> Extend<D> for Database {
+    #[inline]
+    fn extend<T: IntoIterator<Item = D>>(&mut self, iter: T) {
+        self.store_all(iter).expect("Failed to store data");
+    }
+}
+
+impl Data for Database {
+    #[inline]
+    fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+        let conn = self.lock()?;
+        if let Some(path) = conn.path() {
+            links.push_link(("path", path.to_owned()))?;
+        }
+
+        links.push_link(("last_insert_rowid", conn.last_insert_rowid()))?;
+        links.push_link(("last_changes", conn.changes()))?;
+        links.push_link(("autocommit", conn.is_autocommit()))?;
+        links.push_link(("busy", conn.is_busy()))?;
+        drop(conn);
+
+        self.query_links(links, &Default::default())
+    }
+
+    #[inline]
+    fn query_links(&self, links: &mut dyn Links, query: &Query) -> Result<(), LinkError> {
+        self.prepare_query(query)
+            .and_then(|compiled| compiled.links(links))
+            .inspect_err(|e| log::error!("Failed to query links: {e}"))?;
+        Ok(())
+    }
+}
+
+/// A [`Query`] over all data of a [`Database`] whose SQL is generated once.
+///
+/// Created by [`Database::prepare_query`], it can be run repeatedly,
+/// either with the parameters of the original query or with new bindings
+/// for the same placeholders.
+#[derive(Debug)]
+pub struct CompiledQuery {
+    db: Database,
+    sql: SQLBuilder<QueryContext>,
+}
+
+impl CompiledQuery {
+    /// The generated SQL statement
+    #[inline]
+    #[must_use]
+    pub fn sql(&self) -> String {
+        self.sql.to_string()
+    }
+
+    #[inline]
+    pub fn links(&self, links: &mut (impl Links + ?Sized)) -> Result {
+        build_links(&self.db, &self.sql, links, |r| {
+            let id = r.get::<_, SqlID>(0)?;
+            Ok(self.db.get(id.into()))
+        })
+    }
+
+    #[inline]
+    pub fn links_with(&self, params: impl Params, links: &mut (impl Links + ?Sized)) -> Result {
+        build_links_with(&self.db, &self.sql, params, links, |r| {
+            let id = r.get::<_, SqlID>(0)?;
+            Ok(self.db.get(id.into()))
+        })
+    }
+
+    /// Pushes every matching data into `sink` as it is read,
+    /// until `sink` breaks.
+    ///
+    /// The connection stays locked while `sink` runs, so it can't use the database.
+    #[inline]
+    pub fn for_each(&self, sink: impl FnMut(StoredData) -> Result<ControlFlow<()>>) -> Result {
+        let data = |r: &rusqlite::Row| {
+            let id = r.get::<_, SqlID>(0)?;
+            Ok(self.db.get(id.into()))
+        };
+        for_each_row(&self.db, &self.sql, self.sql.params(), data, sink)
+    }
+
+    /// Passes a cursor reading the matching data on demand to `f`.
+    ///
+    /// The connection stays locked while `f` runs, so it can't use the database.
+    #[inline]
+    pub fn cursor<R>(&self, f: impl FnOnce(QueryCursor<'_, StoredData>) -> R) -> Result<R> {
+        let data = |r: &rusqlite::Row| {
+            let id = r.get::<_, SqlID>(0)?;
+            Ok(self.db.get(id.into()))
+        };
+        with_cursor(&self.db, &self.sql, self.sql.params(), data, f)
+    }
+
+    /// Returns the matching data together with their non-NULL values,
+    /// read in a single statement instead of one per data.
+    #[inline]
+    pub fn with_values(&self) -> Result<Vec<(StoredData, Vec<Primitive>)>> {
+        let sql = format!(
+            "SELECT `uuid`, {} FROM `values` WHERE `uuid` IN (SELECT `uuid` FROM ({}))",
+            primitive::all_columns(),
+            self.sql
+        );
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let data = stmt
+            .query_map(self.sql.params(), |r| {
+                let id = r.get::<_, SqlID>(0)?;
+                Ok((self.db.get(id.into()), Primitive::from_row(r, 1)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(data)
+    }
+
+    /// Counts the matching data without fetching it.
+    #[inline]
+    pub fn count(&self) -> Result<u64> {
+        let sql = format!("SELECT COUNT(*) FROM ({})", self.sql);
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let count = stmt.query_row(self.sql.params(), |r| r.get(0))?;
+        Ok(count)
+    }
+
+    #[inline]
+    pub fn ids(&self) -> Result<Vec<ID>> {
+        self.ids_with(self.sql.params())
+    }
+
+    #[inline]
+    pub fn ids_with(&self, params: impl Params) -> Result<Vec<ID>> {
+        let conn = self.db.lock()?;
+        let mut stmt = self.sql.prepare_cached(&conn, &self.db.schema)?;
+        let ids = stmt
+            .query_map(params, |r| r.get::<_, SqlID>(0).map(ID::from))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+}
+
+const VALUES_PAGE_SIZE: usize = 256;
+
+/// Iterator over the id and each non-NULL value of all `values` rows.
+///
+/// Rows are read in pages like [`LinkIter`](crate::storeddata::LinkIter),
+/// so the connection is only locked while a page is fetched.
+#[derive(Debug)]
+pub struct ValuesIter {
+    db: Database,
+    last_rowid: i64,
+    buffer: VecDeque<(ID, Primitive)>,
+    done: bool,
+}
+
+impl ValuesIter {
+    fn fetch_page(&mut self) -> Result {
+        let sql = format!(
+            "SELECT `rowid`, `uuid`, {} FROM `values` WHERE `rowid` > ? ORDER BY `rowid` LIMIT ?",
+            primitive::all_columns()
+        );
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let mut rows = stmt.query(params![self.last_rowid, VALUES_PAGE_SIZE])?;
+
+        let mut count = 0;
+        while let Some(r) = rows.next()? {
+            count += 1;
+            self.last_rowid = r.get(0)?;
+            let id = ID::from(r.get::<_, SqlID>(1)?);
+            for value in Primitive::from_row(r, 2)? {
+                self.buffer.push_back((id, value));
+            }
+        }
+        if count < VALUES_PAGE_SIZE {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ValuesIter {
+    type Item = Result<(ID, Primitive)>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // Rows without any value don't fill the buffer, keep fetching
+        while self.buffer.is_empty() && !self.done {
+            if let Err(e) = self.fetch_page() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+impl FusedIterator for ValuesIter {}
+
+fn hash_value(value: ValueRef, hasher: &mut Fnv1a) {
+    match value {
+        ValueRef::Null => hasher.write(&[0]),
+        ValueRef::Integer(i) => {
+            hasher.write(&[1]);
+            hasher.write(&i.to_le_bytes());
+        }
+        ValueRef::Real(f) => {
+            hasher.write(&[2]);
+            hasher.write(&f.to_bits().to_le_bytes());
+        }
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            let tag = if matches!(value, ValueRef::Text(_)) {
+                3
+            } else {
+                4
+            };
+            hasher.write(&[tag]);
+            hasher.write(&(bytes.len() as u64).to_le_bytes());
+            hasher.write(bytes);
+        }
+    }
+}
+
+static NEXT_SAVEPOINT: AtomicUsize = AtomicUsize::new(0);
+
+/// Savepoint started by [`Database::transaction_savepoint`].
+///
+/// Rolled back when dropped without calling [`Savepoint::commit`].
+pub struct Savepoint<'tx> {
+    db: &'tx Database,
+    tx: &'tx Transaction<'tx>,
+    name: String,
+    done: bool,
+}
+
+impl Savepoint<'_> {
+    /// The enclosing transaction, for running other statements in this savepoint.
+    #[inline]
+    #[must_use]
+    pub fn transaction(&self) -> &Transaction {
+        self.tx
+    }
+
+    #[inline]
+    pub fn store<D: Data + Unique>(&self, data: &D) -> Result {
+        check_writable(self.tx)?;
+        self.db.store_inner(self.tx, data)
+    }
+
+    /// Keeps the changes, they are still discarded if the transaction is rolled back.
+    #[inline]
+    pub fn commit(mut self) -> Result {
+        self.done = true;
+        self.tx.execute_batch(&format!("RELEASE `{}`", self.name))?;
+        Ok(())
+    }
+
+    /// Discards all changes made since the savepoint was started.
+    #[inline]
+    pub fn rollback(mut self) -> Result {
+        self.done = true;
+        self.rollback_inner()
+    }
+
+    fn rollback_inner(&self) -> Result {
+        let name = &self.name;
+        self.tx
+            .execute_batch(&format!("ROLLBACK TO `{name}`; RELEASE `{name}`"))?;
+        Ok(())
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(e) = self.rollback_inner() {
+                log::warn!("Failed to roll back savepoint: {e}");
+            }
+        }
+    }
+}
+
+/// Stores data during [`Database::bulk_load`].
+pub struct BulkLoader<'tx> {
+    db: &'tx Database,
+    tx: &'tx Transaction<'tx>,
+}
+
+impl BulkLoader<'_> {
+    #[inline]
+    pub fn store<D: Data + Unique>(&mut self, data: &D) -> Result {
+        self.db.store_inner(self.tx, data)
+    }
+}
+
+/// [`Links`] storing every pushed link directly, see [`Database::link_sink`].
+pub struct DbLinkSink<'tx>(Inserter<'tx>);
+
+impl Links for DbLinkSink<'_> {
+    #[inline]
+    fn push_unkeyed(&mut self, target: BoxedData) -> LResult {
+        self.0.push_unkeyed(target)
+    }
+
+    #[inline]
+    fn push_keyed(&mut self, target: BoxedData, key: BoxedData) -> LResult {
+        self.0.push_keyed(target, key)
+    }
+
+    #[inline]
+    fn push(&mut self, target: BoxedData, key: Option<BoxedData>) -> LResult {
+        self.0.push(target, key)
+    }
+}
+
+struct Inserter<'tx> {
+    db: &'tx Database,
+    tx: &'tx rusqlite::Transaction<'tx>,
+    source_id: SqlID,
+}
+
+impl Inserter<'_> {
+    /// Stores linked `data` under its own id,
+    /// or a random one if it has none and the `random` feature is enabled.
+    #[inline]
+    fn store_linked(&self, data: BoxedData) -> Result<SqlID> {
+        if let Some(id) = data.get_id() {
+            self.db.store_inner(self.tx, &WithId::new(data, id))?;
+            return Ok(id.into());
+        }
+
+        if let Some(seed) = &self.db.seed {
+            let id = self.db.id_strategy.mint(seed.next_id());
+            self.db.store_inner(self.tx, &WithId::new(data, id))?;
+            return Ok(id.into());
+        }
+
+        #[cfg(feature = "random")]
+        {
+            let data = data.into_unique_random();
+            let id = self.db.id_strategy.mint(data.id());
+            self.db.store_inner(self.tx, &WithId::new(data, id))?;
+            Ok(id.into())
+        }
+        #[cfg(not(feature = "random"))]
+        Err(Error::MissingId)
+    }
+}
+
+impl Links for Inserter<'_> {
+    #[inline]
+    fn push_unkeyed(&mut self, target: BoxedData) -> LResult {
+        let target = self.store_linked(target)?;
+
+        let mut stmt = self
+            .tx
+            .prepare_cached(&self.db.schema.render(INSERT_LINK_UNKEYED))
+            .map_err(LinkError::other)?;
+        stmt.execute([self.source_id, target])
+            .map_err(LinkError::other)?;
+
+        CONTINUE
+    }
+
+    #[inline]
+    fn push_keyed(&mut self, target: BoxedData, key: BoxedData) -> LResult {
+        let target = self.store_linked(target)?;
+        let key = self.store_linked(key)?;
+
+        let mut stmt = self
+            .tx
+            .prepare_cached(&self.db.schema.render(INSERT_LINK_KEYED))
+            .map_err(LinkError::other)?;
+        stmt.execute([self.source_id, target, key])
+            .map_err(LinkError::other)?;
+
+        CONTINUE
+    }
+
+    #[inline]
+    fn push(&mut self, target: BoxedData, key: Option<BoxedData>) -> LResult {
+        if let Some(key) = key {
+            self.push_keyed(target, key)
+        } else {
+            self.push_unkeyed(target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datalink::data::DataExt;
+
+    fn test_db() -> Database {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        // Linked data without an id needs some source of ids
+        #[cfg(not(feature = "random"))]
+        let db = db.with_random_seed(0);
+        db
+    }
+
+    #[test]
+    fn empty() {
+        let db = test_db();
+
+        // No data without a key
+        let list = db.as_list().unwrap();
+        assert_eq!(list.len(), 0);
+
+        let items = db.as_items().unwrap();
+        dbg!(items);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn in_out() {
+        let db = test_db();
+
+        let data = true.into_unique_random();
+        let stored = db.store(&data).unwrap();
+
+        assert_eq!(true, stored.as_bool().unwrap());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn in_out_vec() {
+        let db = test_db();
 
         let data = vec![1, 2, 3];
         let data = data.into_unique_random();
         let stored = db.store(&data).unwrap();
 
-        let list = stored.as_list().unwrap();
-        assert_eq!(list.len(), 3);
+        let list = stored.as_list().unwrap();
+        assert_eq!(list.len(), 3);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn custom_schema() {
+        let schema = Schema {
+            values: "data_values".into(),
+            links: "data_links".into(),
+            uuid: "data_uuid".into(),
+            source_uuid: "data_source".into(),
+            key_uuid: "data_key".into(),
+            target_uuid: "data_target".into(),
+        };
+        let db = Database::open_in_memory().unwrap().with_schema(schema);
+        db.init().unwrap();
+
+        let data = vec![1, 2, 3].into_unique_random();
+        let stored = db.store(&data).unwrap();
+        let list = stored.as_list().unwrap();
+        assert_eq!(list.len(), 3);
+        assert!(db.exists(data.id()).unwrap());
+
+        let data = true.into_unique_random();
+        let stored = db.store(&data).unwrap();
+        assert_eq!(true, stored.as_bool().unwrap());
+
+        let conn = db.conn.lock().unwrap();
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert!(tables.iter().any(|t| t == "data_values"));
+        assert!(tables.iter().any(|t| t == "data_links"));
+        assert!(!tables.iter().any(|t| t == "values" || t == "links"));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn reentrant_store() {
+        let db = test_db();
+        let stored = db.store(&vec![1, 2].into_unique_random()).unwrap();
+
+        // Reading `stored` while storing it needs the lock held by `store`
+        let err = db.store(&stored).unwrap_err();
+        assert!(matches!(err, Error::DataLink(_)), "{err:?}");
+
+        // The lock is released again
+        assert!(db.exists(stored.id()).unwrap());
+        assert!(db.lock().unwrap().is_autocommit());
+        assert!(matches!(
+            db.lock().and_then(|_guard| db.lock().map(drop)),
+            Err(Error::Reentrant)
+        ));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn insert_unique() {
+        let db = test_db();
+
+        let data = true.into_unique_random();
+
+        db.store(&data).unwrap();
+        let stored = db.store(&data).unwrap();
+
+        assert_eq!(true, stored.as_bool().unwrap());
+    }
+
+    #[test]
+    fn pragmas() {
+        let db = Database::with_pragmas(Connection::open_in_memory().unwrap()).unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        let foreign_keys: bool = conn
+            .pragma_query_value(None, "foreign_keys", |r| r.get(0))
+            .unwrap();
+        assert!(foreign_keys);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn optimize() {
+        let db = test_db();
+        db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+
+        db.optimize().unwrap();
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn timestamps() {
+        const SQL: &str = "SELECT `created_at`, `updated_at` FROM `values` WHERE `uuid` = ?";
+        let db = test_db();
+        let data = true.into_unique_random();
+        let id = SqlID::from(data.id());
+        let timestamps = || -> (String, String) {
+            let conn = db.conn.lock().unwrap();
+            conn.query_row(SQL, [id], |r| Ok((r.get(0)?, r.get(1)?)))
+                .unwrap()
+        };
+
+        db.store(&data).unwrap();
+        let (created, updated) = timestamps();
+        assert_eq!(created, updated);
+
+        std::thread::sleep(Duration::from_millis(10));
+
+        db.store(&data).unwrap();
+        let (created_again, updated_again) = timestamps();
+        assert_eq!(created, created_again);
+        assert!(updated_again > updated);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn data_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        let db = Database::open(&path).unwrap();
+        db.init().unwrap();
+        let other = Database::open(&path).unwrap();
+
+        let before = db.data_version().unwrap();
+        assert_eq!(before, db.data_version().unwrap());
+
+        other.store(&true.into_unique_random()).unwrap();
+
+        assert_ne!(before, db.data_version().unwrap());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn clear_statement_cache() {
+        let db = test_db();
+        let stored = db.store(&vec!["a", "b"].into_unique_random()).unwrap();
+        assert_eq!(stored.as_list().unwrap().len(), 2);
+        assert_eq!(stored.as_list().unwrap()[0].as_str().unwrap(), "a");
+
+        db.clear_statement_cache().unwrap();
+
+        assert_eq!(stored.as_list().unwrap().len(), 2);
+        assert_eq!(stored.as_list().unwrap()[1].as_str().unwrap(), "b");
+
+        let nested = db.transaction_with(|_| db.clear_statement_cache());
+        assert!(matches!(nested, Err(Error::Reentrant)), "{nested:?}");
+        let nested = db.transaction_with(|_| db.set_trace(true));
+        assert!(matches!(nested, Err(Error::Reentrant)), "{nested:?}");
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn trace() {
+        crate::test_util::capture_logs();
+        let db = test_db();
+
+        db.set_trace(true).unwrap();
+        db.store(&true.into_unique_random()).unwrap();
+        db.set_trace(false).unwrap();
+        db.store(&false.into_unique_random()).unwrap();
+
+        let logs = crate::test_util::take_logs(SQL_LOG_TARGET);
+        let inserts = logs
+            .iter()
+            .filter(|l| l.contains("INSERT INTO `values`"))
+            .count();
+        // Once traced and once profiled, the second store isn't logged
+        assert_eq!(inserts, 2);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn prepared_query() {
+        use datalink::query::{DataFilter, LinkFilter};
+
+        let db = test_db();
+        let a = db.store(&true.into_unique_random()).unwrap();
+        let b = db.store(&false.into_unique_random()).unwrap();
+
+        let query = Query::new(LinkFilter::Target(DataFilter::Id(a.id()).into()));
+        let compiled = db.prepare_query(&query).unwrap();
+
+        assert_eq!(compiled.ids().unwrap(), [a.id()]);
+        assert_eq!(compiled.ids_with([SqlID::from(b.id())]).unwrap(), [b.id()]);
+        assert_eq!(compiled.ids_with([SqlID::from(a.id())]).unwrap(), [a.id()]);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn query_ids_filtered() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        let apple = db.store(&"apple".into_unique_random()).unwrap();
+        let another = db.store(&"apple".into_unique_random()).unwrap();
+        let banana = db.store(&"banana".into_unique_random()).unwrap();
+
+        let query = Query::new(Link::target(Data::text("apple")));
+        let ids = db.query_ids_filtered(&query).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&apple.id()));
+        assert!(ids.contains(&another.id()));
+
+        let query = Query::new(Link::target(!Data::text("apple")));
+        assert_eq!(db.query_ids_filtered(&query).unwrap(), [banana.id()]);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn collect() {
+        let items: Vec<_> = [true, false, true]
+            .into_iter()
+            .map(|b| b.into_unique_random())
+            .collect();
+        let ids: Vec<_> = items.iter().map(|d| d.id()).collect();
+
+        let mut db: Database = items.into_iter().collect();
+        for (id, expected) in ids.iter().zip([true, false, true]) {
+            assert_eq!(db.get(*id).as_bool(), Some(expected));
+        }
+
+        let more = false.into_unique_random();
+        let id = more.id();
+        db.extend([more]);
+        assert_eq!(db.get(id).as_bool(), Some(false));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn bulk_load() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        let index_count = || -> i64 {
+            let conn = db.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap()
+        };
+        let indexes = index_count();
+
+        db.bulk_load(|loader| {
+            for i in 0..10_000 {
+                loader.store(&i.to_string().into_unique_random())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(index_count(), indexes);
+
+        let query = Query::new(Link::target(Data::text("9999")));
+        assert_eq!(db.query_ids_filtered(&query).unwrap().len(), 1);
+
+        let stored = db.store(&true.into_unique_random()).unwrap();
+        let err = db.bulk_load(|loader| {
+            loader.store(&false.into_unique_random())?;
+            Err(Error::InvalidQuery("abort".into()))
+        });
+        assert!(matches!(err, Err(Error::InvalidQuery(_))));
+        assert_eq!(index_count(), indexes);
+        assert!(db.exists(stored.id()).unwrap());
+
+        let conn = db.conn.lock().unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM `values`", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 10_001);
+        let synchronous: i32 = conn
+            .pragma_query_value(None, "synchronous", |r| r.get(0))
+            .unwrap();
+        assert_ne!(synchronous, 0);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn wal_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        let db = Database::open(&path).unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "journal_mode", "WAL")
+            .unwrap();
+        db.init().unwrap();
+        db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+
+        let wal = dir.path().join("data.sqlite-wal");
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        let (log, checkpointed) = db
+            .wal_checkpoint(CheckpointMode::Truncate)
+            .unwrap()
+            .unwrap();
+        assert_eq!(log, checkpointed);
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+        // Rollback journal
+        let path = dir.path().join("journal.sqlite");
+        let db = Database::open(&path).unwrap();
+        db.init().unwrap();
+        assert_eq!(db.wal_checkpoint(CheckpointMode::Passive).unwrap(), None);
+        assert_eq!(db.wal_checkpoint(CheckpointMode::Truncate).unwrap(), None);
+    }
+
+    #[derive(Debug)]
+    struct Explicit(Vec<ID>);
+
+    impl Data for Explicit {
+        fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+            for (i, id) in self.0.iter().enumerate() {
+                links.push_unkeyed(Box::new(WithId::new(i as i32, *id)))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn explicit_ids() {
+        let db = test_db();
+        let ids: Vec<ID> = ["2", "3"].iter().map(|s| s.parse().unwrap()).collect();
+        let data = WithId::new(Explicit(ids.clone()), "1".parse().unwrap());
+
+        let stored = db.store(&data).unwrap();
+        let targets: Vec<_> = stored
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(_, target)| target.id())
+            .collect();
+        assert_eq!(targets, ids);
+        assert_eq!(db.get(ids[1]).as_i32(), Some(1));
+    }
+
+    #[test]
+    fn bad_id_row() {
+        struct Discard;
+
+        impl Links for Discard {
+            fn push_unkeyed(&mut self, _target: BoxedData) -> LResult {
+                CONTINUE
+            }
+
+            fn push_keyed(&mut self, _target: BoxedData, _key: BoxedData) -> LResult {
+                CONTINUE
+            }
+
+            fn push(&mut self, _target: BoxedData, _key: Option<BoxedData>) -> LResult {
+                CONTINUE
+            }
+        }
+
+        let db = test_db();
+        db.store(&WithId::new(true, "1".parse().unwrap())).unwrap();
+        // Passes the length check but isn't a valid id
+        db.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO `values` (`uuid`) VALUES ('not a valid id!!')",
+                [],
+            )
+            .unwrap();
+
+        let compiled = db.prepare_query(&Query::default()).unwrap();
+        let err = compiled.ids().unwrap_err();
+        assert!(
+            matches!(err, Error::Sql(rusqlite::Error::InvalidColumnType(..))),
+            "{err:?}"
+        );
+
+        // The bad row ends the query with an error instead of a panic
+        let err = db.query_links(&mut Discard, &Query::default()).unwrap_err();
+        assert!(matches!(err, LinkError::Other(_)), "{err:?}");
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn transaction_with() {
+        const INSERT: &str = "INSERT INTO `values` (`uuid`) VALUES (?)";
+
+        let db = test_db();
+        let committed = true.into_unique_random().id();
+        let rolled_back = false.into_unique_random().id();
+
+        let rows = db
+            .transaction_with(|tx| Ok(tx.execute(INSERT, [SqlID::from(committed)])?))
+            .unwrap();
+        assert_eq!(rows, 1);
+
+        let err = db.transaction_with(|tx| {
+            tx.execute(INSERT, [SqlID::from(rolled_back)])?;
+            Err::<(), _>(Error::InvalidQuery("abort".into()))
+        });
+        assert!(matches!(err, Err(Error::InvalidQuery(_))));
+
+        assert!(db.exists(committed).unwrap());
+        assert!(!db.exists(rolled_back).unwrap());
     }
 
     #[test]
-    fn insert_unique() {
+    fn links_between() {
+        /// Links "1" to "2" keyed by "3" and "4", and unkeyed
+        #[derive(Debug)]
+        struct Pair;
+
+        impl Data for Pair {
+            fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+                let data = |id: &str| Box::new(WithId::new(id.to_owned(), id.parse().unwrap()));
+                links.push_keyed(data("2"), data("3"))?;
+                links.push_keyed(data("2"), data("4"))?;
+                links.push_keyed(data("5"), data("3"))?;
+                links.push_unkeyed(data("2"))?;
+                Ok(())
+            }
+        }
+
         let db = test_db();
+        let [one, two, three, four] = ["1", "2", "3", "4"].map(|id| id.parse::<ID>().unwrap());
+        db.store(&WithId::new(Pair, one)).unwrap();
 
-        let data = true.into_unique_random();
+        assert_eq!(
+            db.links_between(one, two).unwrap(),
+            [Some(three), Some(four), None]
+        );
+        assert!(db.links_between(two, one).unwrap().is_empty());
+        assert!(db.links_between(one, three).unwrap().is_empty());
+    }
 
-        db.store(&data).unwrap();
+    #[test]
+    fn store_in() {
+        let db = test_db();
+        let [a, b] = ["1", "2"].map(|id| id.parse::<ID>().unwrap());
+
+        let res = db.transaction_with(|tx| {
+            db.store_in(tx, &WithId::new("a", a))?;
+            db.store_in(tx, &WithId::new(vec![1, 2], b))?;
+            Err::<(), _>(Error::InvalidQuery("abort".into()))
+        });
+        assert!(res.is_err());
+        assert!(!db.exists(a).unwrap());
+        assert!(!db.exists(b).unwrap());
+        assert_eq!(db.count_query(&Query::default()).unwrap(), 0);
+
+        let stored = db
+            .transaction_with(|tx| {
+                db.store_in(tx, &WithId::new("a", a))?;
+                db.store_in(tx, &WithId::new(vec![1, 2], b))
+            })
+            .unwrap();
+        assert_eq!(db.get(a).as_str().as_deref(), Some("a"));
+        assert_eq!(stored.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn transaction_savepoint() {
+        let db = test_db();
+        let [kept, rolled_back, dropped] = ["1", "2", "3"].map(|id| id.parse::<ID>().unwrap());
+
+        db.transaction_with(|tx| {
+            let sp = db.transaction_savepoint(tx)?;
+            sp.store(&WithId::new(true, kept))?;
+            sp.commit()?;
+
+            let sp = db.transaction_savepoint(tx)?;
+            sp.store(&WithId::new(false, rolled_back))?;
+            sp.rollback()?;
+
+            let sp = db.transaction_savepoint(tx)?;
+            sp.store(&WithId::new(1, dropped))?;
+            drop(sp);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(db.get(kept).as_bool(), Some(true));
+        assert!(!db.exists(rolled_back).unwrap());
+        assert!(!db.exists(dropped).unwrap());
+    }
+
+    #[test]
+    fn random_seed() {
+        let minted = |seed: u64| -> Vec<ID> {
+            let db = test_db().with_random_seed(seed);
+            let data = WithId::new(vec!["a", "b", "c"], "1".parse().unwrap());
+            let stored = db.store(&data).unwrap();
+            stored
+                .entries()
+                .unwrap()
+                .iter()
+                .map(|(_, t)| t.id())
+                .collect()
+        };
+
+        let ids = minted(42);
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids, minted(42));
+        assert_ne!(ids, minted(43));
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn time_ordered_ids() {
+        let db = test_db().with_id_strategy(IdStrategy::TimeOrdered);
+        let stored = db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+
+        let ids: Vec<u128> = stored
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(_, target)| target.id().as_raw().get())
+            .collect();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn newer_schema() {
+        let db = test_db();
+        assert_eq!(db.compatibility().unwrap(), Compatibility::Exact);
+        let stored = db.store(&true.into_unique_random()).unwrap();
+
+        db.conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "user_version", crate::schema_version!() + 1)
+            .unwrap();
+        assert_eq!(db.compatibility().unwrap(), Compatibility::Newer);
+
+        assert_eq!(db.get(stored.id()).as_bool(), Some(true));
+        let err = db.store(&false.into_unique_random()).unwrap_err();
+        assert!(matches!(err, Error::SchemaTooNew), "{err:?}");
+
+        let too_new = |res: Result<_>| matches!(res, Err(Error::SchemaTooNew));
+        let id = stored.id();
+        assert!(too_new(db.bulk_load(|_| Ok(()))));
+        assert!(too_new(db.rename_id(id, "1".parse().unwrap()).map(drop)));
+        assert!(too_new(db.repair_orphans().map(drop)));
+        assert!(too_new(db.prune_unreferenced(&[id]).map(drop)));
+        assert!(too_new(
+            db.transaction_with(|tx| db.store_in(tx, &stored).map(drop))
+        ));
+        assert!(too_new(stored.set_value(false)));
+        assert!(too_new(stored.replace(&false)));
+        assert!(too_new(stored.unlink(None, id).map(drop)));
+        assert!(too_new(stored.merge_into(&db.get("1".parse().unwrap()))));
+        #[cfg(feature = "migrations")]
+        assert!(too_new(db.migrate()));
+        assert_eq!(db.get(stored.id()).as_bool(), Some(true));
+
+        // Opening leaves the newer schema alone
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("newer.sqlite");
+        let newer = Database::open(&path).unwrap();
+        newer.init().unwrap();
+        newer
+            .conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "user_version", crate::schema_version!() + 1)
+            .unwrap();
+        drop(newer);
+        let newer = Database::open_initialized(&path).unwrap();
+        assert_eq!(newer.compatibility().unwrap(), Compatibility::Newer);
+        assert_eq!(
+            newer.schema_version().unwrap(),
+            crate::schema_version!() + 1
+        );
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn nested_store() {
+        let db = test_db();
+        let err = db
+            .transaction_with(|_| db.store(&true.into_unique_random()).map(drop))
+            .unwrap_err();
+        assert!(matches!(err, Error::Reentrant), "{err:?}");
+    }
+
+    #[test]
+    fn poisoned_lock() {
+        let db = test_db();
+        let clone = db.clone();
+        let res = std::thread::spawn(move || {
+            clone
+                .transaction_with(|tx| -> Result {
+                    tx.execute(
+                        "INSERT INTO `values` (`uuid`) VALUES (?)",
+                        [SqlID::from("2".parse::<ID>().unwrap())],
+                    )?;
+                    panic!("poisons the lock");
+                })
+                .unwrap();
+        })
+        .join();
+        assert!(res.is_err());
+        assert!(db.conn.is_poisoned());
+
+        let id: ID = "1".parse().unwrap();
+        db.store(&WithId::new(true, id)).unwrap();
+        assert_eq!(db.get(id).as_bool(), Some(true));
+        assert!(!db.exists("2".parse().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn foreign_keys() {
+        let db = test_db();
+        assert!(db.foreign_keys_enabled().unwrap());
+
+        db.with_foreign_keys(false).unwrap();
+        assert!(!db.foreign_keys_enabled().unwrap());
+
+        db.with_foreign_keys(true).unwrap();
+        assert!(db.foreign_keys_enabled().unwrap());
+    }
+
+    #[test]
+    fn apply_pragmas() {
+        let db = test_db();
+        db.apply_pragmas(&[("cache_size", "-4000"), ("TEMP_STORE", "MEMORY")])
+            .unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        let cache_size: i64 = conn
+            .pragma_query_value(None, "cache_size", |r| r.get(0))
+            .unwrap();
+        let temp_store: i64 = conn
+            .pragma_query_value(None, "temp_store", |r| r.get(0))
+            .unwrap();
+        drop(conn);
+        assert_eq!(cache_size, -4000);
+        assert_eq!(temp_store, 2);
+
+        let err = db
+            .apply_pragmas(&[("cache_size", "-8000"), ("user_version = 0; --", "")])
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidPragma(_)), "{err:?}");
+        let conn = db.conn.lock().unwrap();
+        let cache_size: i64 = conn
+            .pragma_query_value(None, "cache_size", |r| r.get(0))
+            .unwrap();
+        assert_eq!(cache_size, -4000);
+    }
+
+    #[test]
+    fn set_cache_size() {
+        let cache_size = |db: &Database| -> i64 {
+            let conn = db.conn.lock().unwrap();
+            conn.pragma_query_value(None, "cache_size", |r| r.get(0))
+                .unwrap()
+        };
+        let db = test_db();
+
+        db.set_cache_size(CacheSize::mib(16)).unwrap();
+        assert_eq!(cache_size(&db), -16 * 1024);
+        assert_eq!(CacheSize::mib(u32::MAX), CacheSize::KiB(u32::MAX));
+
+        db.set_cache_size(CacheSize::Pages(500)).unwrap();
+        assert_eq!(cache_size(&db), 500);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn count_query() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        db.store(&vec!["foo", "bar", "bar"].into_unique_random())
+            .unwrap();
+
+        for query in [
+            Query::default(),
+            Query::new(Link::target(Data::text("bar"))),
+            Query::new(Link::target(Data::text("qux"))),
+        ] {
+            let count = db.count_query(&query).unwrap();
+            let ids = db.query_ids_filtered(&query).unwrap();
+            assert_eq!(count, ids.len() as u64);
+        }
+        let query = Query::new(Link::target(Data::text("bar")));
+        assert_eq!(db.count_query(&query).unwrap(), 2);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn prune_unreferenced() {
+        #[derive(Debug)]
+        struct Root;
+
+        impl Data for Root {
+            fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+                links.push_link(("list", vec![1, 2]))
+            }
+        }
+
+        let db = test_db();
+        let root = db.store(&Root.into_unique_random()).unwrap();
+        let unreachable = db.store(&vec![3, 4, 5].into_unique_random()).unwrap();
+
+        assert!(matches!(db.prune_unreferenced(&[]), Err(Error::NoRoots)));
+        // The unreachable list, its items and its links
+        assert_eq!(db.prune_unreferenced(&[root.id()]).unwrap(), 4 + 3);
+        assert!(!db.exists(unreachable.id()).unwrap());
+        // The root, its key and the list with its items
+        assert_eq!(db.count_query(&Query::default()).unwrap(), 5);
+        let (key, list) = &root.entries().unwrap()[0];
+        assert_eq!(key.as_ref().unwrap().as_str().unwrap(), "list");
+        assert_eq!(list.as_list().unwrap().len(), 2);
+
+        assert_eq!(db.prune_unreferenced(&[root.id()]).unwrap(), 0);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn import_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("other.sqlite");
+        let other = Database::open(&path).unwrap();
+        other.init().unwrap();
+        other.store(&vec![1, 2].into_unique_random()).unwrap();
+        drop(other);
+
+        let db = test_db();
+        db.store(&"kept".into_unique_random()).unwrap();
+        assert_eq!(db.import_from_file(&path).unwrap(), 3 + 2);
+        assert_eq!(db.count_query(&Query::default()).unwrap(), 4);
+
+        // Importing again copies nothing
+        assert_eq!(db.import_from_file(&path).unwrap(), 0);
+        let conn = db.conn.lock().unwrap();
+        let links: i64 = conn
+            .query_row("SELECT COUNT(*) FROM `links`", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(links, 2);
+        drop(conn);
+
+        let missing = dir.path().join("missing.sqlite");
+        let err = db.import_from_file(&missing).unwrap_err();
+        assert!(
+            matches!(&err, Error::NotFound(p) if *p == missing),
+            "{err:?}"
+        );
+        assert!(!missing.exists());
+
+        let empty = dir.path().join("empty.sqlite");
+        std::fs::File::create(&empty).unwrap();
+        let err = db.import_from_file(&empty).unwrap_err();
+        assert!(matches!(err, Error::SchemaMismatch(_)), "{err:?}");
+
+        // Current version, but a different shape
+        let other = Connection::open(&path).unwrap();
+        other
+            .execute_batch("ALTER TABLE `values` ADD COLUMN `extra` TEXT;")
+            .unwrap();
+        drop(other);
+        let err = db.import_from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::SchemaMismatch(_)), "{err:?}");
+        assert_eq!(db.count_query(&Query::default()).unwrap(), 4);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn set_locking_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        let db = Database::open(&path).unwrap();
+        db.init().unwrap();
+        let other = Connection::open(&path).unwrap();
+        other.busy_timeout(Duration::ZERO).unwrap();
+        let other_can_read = || {
+            other
+                .query_row("SELECT COUNT(*) FROM `values`", [], |r| r.get::<_, i64>(0))
+                .is_ok()
+        };
+
+        db.set_locking_mode(LockingMode::Exclusive).unwrap();
+        let stored = db.store(&"locked".into_unique_random()).unwrap();
+        assert_eq!(stored.as_str().as_deref(), Some("locked"));
+        assert!(!other_can_read());
+
+        db.set_locking_mode(LockingMode::Normal).unwrap();
+        assert!(db.exists(stored.id()).unwrap());
+        assert!(other_can_read());
+    }
+
+    #[test]
+    fn immediate_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        Database::open(&path).unwrap().init().unwrap();
+        let other = Connection::open(&path).unwrap();
+        other.busy_timeout(Duration::ZERO).unwrap();
+        let other_can_write = || {
+            let locked = other.execute_batch("BEGIN IMMEDIATE; ROLLBACK;").is_err();
+            !locked
+        };
+
+        let deferred = Database::open(&path).unwrap();
+        deferred
+            .transaction_with(|_| {
+                assert!(other_can_write());
+                Ok(())
+            })
+            .unwrap();
+
+        let immediate = Database::open(&path)
+            .unwrap()
+            .with_transaction_behavior(TransactionBehavior::Immediate);
+        immediate
+            .transaction_with(|_| {
+                assert!(!other_can_write());
+                Ok(())
+            })
+            .unwrap();
+        assert!(other_can_write());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn rename_id() {
+        let db = test_db();
+        let list = db.store(&vec![1, 2].into_unique_random()).unwrap();
+        let entries = list.entries().unwrap();
+        let old = entries[0].1.id();
+        let new: ID = "1".parse().unwrap();
+
+        assert!(!db.rename_id(old, new).unwrap());
+        assert!(!db.exists(old).unwrap());
+        let entries = list.entries().unwrap();
+        assert_eq!(entries[0].1.id(), new);
+        assert_eq!(entries[0].1.as_i32(), Some(1));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn rename_id_merge() {
+        let db = test_db();
+        let list = db.store(&vec![1, 2].into_unique_random()).unwrap();
+        let other = db.store(&vec![3].into_unique_random()).unwrap();
+        let entries = list.entries().unwrap();
+        let (one, two) = (entries[0].1.id(), entries[1].1.id());
+        db.get(two).set_value("two").unwrap();
+
+        // Both list targets become the same data
+        assert!(db.rename_id(two, one).unwrap());
+        assert!(!db.exists(two).unwrap());
+        let merged = db.get(one);
+        assert_eq!(merged.as_i32(), Some(1));
+        assert_eq!(merged.as_str().as_deref(), Some("two"));
+        let targets: Vec<_> = list
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(_, target)| target.id())
+            .collect();
+        assert_eq!(targets, [one, one]);
+
+        // Links of both sources are kept
+        assert!(db.rename_id(other.id(), list.id()).unwrap());
+        assert_eq!(list.entries().unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn rename_id_merge_links_only() {
+        let db = test_db();
+        let to = db.store(&vec![1].into_unique_random()).unwrap();
+        db.execute_raw(
+            "DELETE FROM `values` WHERE `uuid` = ?",
+            [SqlID::from(to.id())],
+        )
+        .unwrap();
+        let from = db.store(&"kept".into_unique_random()).unwrap();
+
+        assert!(db.rename_id(from.id(), to.id()).unwrap());
+        assert!(!db.exists(from.id()).unwrap());
+        assert_eq!(to.as_str().as_deref(), Some("kept"));
+        assert_eq!(to.entries().unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn values_iter() {
+        let db = test_db();
+        let list = db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+        let text = db.store(&"text".into_unique_random()).unwrap();
+        text.set_value(true).unwrap();
+        let many = (0..1000).collect::<Vec<u16>>().into_unique_random();
+        db.store(&many).unwrap();
+
+        let values = db.values_iter().collect::<Result<Vec<_>>>().unwrap();
+        // The lists themselves have no value
+        assert_eq!(values.len(), 3 + 2 + 1000);
+        assert!(!values.iter().any(|(id, _)| *id == list.id()));
+
+        let of_text: Vec<_> = values
+            .iter()
+            .filter(|(id, _)| *id == text.id())
+            .map(|(_, v)| v.clone())
+            .collect();
+        assert_eq!(of_text, [Primitive::Bool(true), Primitive::from("text")]);
+        let ints = values
+            .iter()
+            .filter(|(_, v)| matches!(v, Primitive::I32(_)))
+            .count();
+        assert_eq!(ints, 3);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn query_map_to_vec() {
+        let db = test_db();
+        let a = db.store(&"a".into_unique_random()).unwrap().id();
+        let b = db.store(&"b".into_unique_random()).unwrap().id();
+
+        let ids = db
+            .query_map_to_vec(
+                "SELECT `uuid` FROM `values` WHERE `str` IS NOT NULL ORDER BY `str`",
+                [],
+                |r| Ok(ID::from(r.get::<_, SqlID>(0)?).to_string()),
+            )
+            .unwrap();
+        assert_eq!(ids, [a.to_string(), b.to_string()]);
+    }
+
+    #[test]
+    fn execute_raw() {
+        let db = test_db();
+        let id = SqlID::from("1".parse::<ID>().unwrap());
+        let changed = db
+            .execute_raw(
+                "INSERT INTO `values` (`uuid`, `str`) VALUES (?, 'raw')",
+                [id],
+            )
+            .unwrap();
+        assert_eq!(changed, 1);
+        assert_eq!(db.get(id.into()).as_str().as_deref(), Some("raw"));
+
+        let changed = db.execute_raw("UPDATE `values` SET `u8` = 1", []).unwrap();
+        assert_eq!(changed, 1);
+
+        let err = db
+            .execute_raw("DELETE FROM `values`; DELETE FROM `links`", [])
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Sql(rusqlite::Error::MultipleStatement)),
+            "{err:?}"
+        );
+        assert!(db.exists(id.into()).unwrap());
+    }
+
+    #[test]
+    fn content_hash() {
+        let data = |id: &str, targets: &[&str]| {
+            let targets = targets.iter().map(|t| t.parse().unwrap()).collect();
+            WithId::new(Explicit(targets), id.parse().unwrap())
+        };
+        let a = test_db();
+        let b = test_db();
+        a.store(&data("1", &["3", "4"])).unwrap();
+        a.store(&data("2", &["5"])).unwrap();
+        // Stored in a different order and at a different time
+        std::thread::sleep(Duration::from_millis(2));
+        b.store(&data("2", &["5"])).unwrap();
+        b.store(&data("1", &["3", "4"])).unwrap();
+        assert_eq!(a.content_hash().unwrap(), b.content_hash().unwrap());
+
+        b.get("3".parse().unwrap()).set_value("changed").unwrap();
+        assert_ne!(a.content_hash().unwrap(), b.content_hash().unwrap());
+
+        let c = test_db();
+        c.store(&data("1", &["3", "4", "5"])).unwrap();
+        c.store(&data("2", &[])).unwrap();
+        assert_ne!(a.content_hash().unwrap(), c.content_hash().unwrap());
+
+        // Fixed across builds: FNV-1a of both zero row counts
+        assert_eq!(test_db().content_hash().unwrap(), 0x8820_1fb9_60ff_6465);
+    }
+
+    #[test]
+    fn repair_orphans() {
+        let db = test_db();
+        let data = WithId::new(Explicit(vec!["2".parse().unwrap()]), "1".parse().unwrap());
         let stored = db.store(&data).unwrap();
+        assert_eq!(db.repair_orphans().unwrap(), 0);
 
-        assert_eq!(true, stored.as_bool().unwrap());
+        let missing = SqlID::from("3".parse::<ID>().unwrap());
+        db.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO `links` (`source_uuid`, `target_uuid`) VALUES (?, ?)",
+                [SqlID::from(stored.id()), missing],
+            )
+            .unwrap();
+        assert_eq!(stored.entries().unwrap().len(), 2);
+
+        assert_eq!(db.repair_orphans().unwrap(), 1);
+        assert_eq!(stored.entries().unwrap().len(), 1);
+        assert_eq!(db.repair_orphans().unwrap(), 0);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn snapshot_to_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(dir.path().join("data.sqlite")).unwrap();
+        db.init().unwrap();
+        let stored = db.store(&vec![1, 2].into_unique_random()).unwrap();
+
+        let snapshot = db.snapshot_to_memory().unwrap();
+        assert_eq!(snapshot.compatibility().unwrap(), Compatibility::Exact);
+
+        db.store(&vec![3].into_unique_random()).unwrap();
+        db.get(stored.id()).set_value(true).unwrap();
+
+        let copy = snapshot.get(stored.id());
+        assert_eq!(copy.entries().unwrap().len(), 2);
+        assert_eq!(copy.as_bool(), None);
+        assert_eq!(snapshot.count_query(&Query::default()).unwrap(), 3);
+        assert_eq!(db.count_query(&Query::default()).unwrap(), 5);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn open_initialized() {
+        let db = Database::open_in_memory_initialized().unwrap();
+        assert_eq!(db.compatibility().unwrap(), Compatibility::Exact);
+        let stored = db.store(&"ready".into_unique_random()).unwrap();
+        assert_eq!(stored.as_str().as_deref(), Some("ready"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        let db = Database::open_initialized(&path).unwrap();
+        let id = db.store(&1.into_unique_random()).unwrap().id();
+        drop(db);
+
+        // Reopening an initialized database keeps its data
+        let db = Database::open_initialized(&path).unwrap();
+        assert_eq!(db.get(id).as_i32(), Some(1));
+    }
+
+    #[test]
+    fn path() {
+        let db = test_db();
+        assert!(db.is_memory().unwrap());
+        assert_eq!(db.path().unwrap(), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        let db = Database::open(&path).unwrap();
+        assert!(!db.is_memory().unwrap());
+        assert_eq!(
+            db.path().unwrap().unwrap().canonicalize().unwrap(),
+            path.canonicalize().unwrap()
+        );
+
+        let nested = db.transaction_with(|_| db.path());
+        assert!(matches!(nested, Err(Error::Reentrant)), "{nested:?}");
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn open_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        assert!(Database::open_read_only(&path).is_err());
+
+        let db = Database::open(&path).unwrap();
+        db.init().unwrap();
+        let id = db.store(&"kept".into_unique_random()).unwrap().id();
+        assert!(!db.is_read_only().unwrap());
+        drop(db);
+
+        let db = Database::open_read_only(&path).unwrap();
+        assert!(db.is_read_only().unwrap());
+        assert_eq!(db.get(id).as_str().as_deref(), Some("kept"));
+        assert_eq!(db.count_query(&Query::default()).unwrap(), 1);
+
+        let err = db.store(&"new".into_unique_random()).unwrap_err();
+        assert!(matches!(err, Error::ReadOnly), "{err:?}");
+        assert!(matches!(db.init(), Err(Error::ReadOnly)));
+        #[cfg(feature = "migrations")]
+        assert!(matches!(db.migrate(), Err(Error::ReadOnly)));
+    }
+
+    #[test]
+    fn open_with_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sqlite");
+        let config = DbConfig {
+            journal_mode: Some(JournalMode::Wal),
+            busy_timeout: Duration::from_millis(1234),
+            cache_size: Some(CacheSize::Pages(500)),
+            foreign_keys: false,
+            id_strategy: IdStrategy::TimeOrdered,
+            transaction_behavior: TransactionBehavior::Immediate,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let db = Database::open_with_config(&path, config).unwrap();
+
+        assert!(!db.foreign_keys_enabled().unwrap());
+        assert_eq!(db.id_strategy(), IdStrategy::TimeOrdered);
+        let debug = format!("{db:?}");
+        assert!(debug.contains("Immediate") && debug.contains("seeded: true"));
+        let conn = db.lock().unwrap();
+        let journal_mode: String = conn
+            .pragma_query_value(None, "journal_mode", |r| r.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+        let pragma = |name| conn.pragma_query_value(None, name, |r| r.get::<_, i64>(0));
+        assert_eq!(pragma("busy_timeout").unwrap(), 1234);
+        assert_eq!(pragma("cache_size").unwrap(), 500);
+        drop(conn);
+
+        let default = Database::open_with_config(&path, DbConfig::default()).unwrap();
+        assert!(default.foreign_keys_enabled().unwrap());
+        assert_eq!(default.id_strategy(), IdStrategy::default());
+    }
+
+    #[test]
+    fn detect_schema() {
+        let db = Database::open_in_memory().unwrap();
+        assert_eq!(db.detect_schema().unwrap(), SchemaState::Empty);
+        db.init().unwrap();
+        assert_eq!(db.detect_schema().unwrap(), SchemaState::Current);
+
+        let legacy = Database::open_in_memory().unwrap();
+        legacy
+            .conn
+            .lock()
+            .unwrap()
+            .execute_batch(include_str!("migrations/1.sql"))
+            .unwrap();
+        assert_eq!(legacy.detect_schema().unwrap(), SchemaState::Legacy(1));
+        #[cfg(feature = "migrations")]
+        {
+            legacy.migrate().unwrap();
+            assert_eq!(legacy.detect_schema().unwrap(), SchemaState::Current);
+        }
+
+        let other = Database::open_in_memory().unwrap();
+        other
+            .conn
+            .lock()
+            .unwrap()
+            .execute_batch("CREATE TABLE `users` (`name` TEXT)")
+            .unwrap();
+        assert_eq!(other.detect_schema().unwrap(), SchemaState::NotDatalink);
+
+        // Tables of version 3, but version 1 recorded
+        db.conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "user_version", 1)
+            .unwrap();
+        assert_eq!(db.detect_schema().unwrap(), SchemaState::Unknown);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn clone_schema_to() {
+        const TABLES: &str =
+            "SELECT type, name FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' ORDER BY name";
+        fn objects(db: &Database) -> Vec<(String, String)> {
+            let conn = db.conn.lock().unwrap();
+            let mut stmt = conn.prepare(TABLES).unwrap();
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db();
+        db.store(&vec![1, 2].into_unique_random()).unwrap();
+
+        let clone = db.clone_schema_to(dir.path().join("clone.sqlite")).unwrap();
+        assert_eq!(
+            clone.schema_version().unwrap(),
+            db.schema_version().unwrap()
+        );
+        assert_eq!(objects(&clone), objects(&db));
+        assert_eq!(clone.count_query(&Query::default()).unwrap(), 0);
+
+        clone.store(&vec![3].into_unique_random()).unwrap();
+        assert_eq!(clone.count_query(&Query::default()).unwrap(), 2);
+    }
+
+    #[test]
+    fn link_sink() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        let source: ID = "1".parse().unwrap();
+        db.link_sink(source, |sink| {
+            sink.push_link(("a", 1))?;
+            sink.push_link(("b", 2))?;
+            sink.push_unkeyed(Box::new("c"))?;
+            Ok(())
+        })
+        .unwrap();
+
+        let stored = db.get(source);
+        let entries = stored.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[1].0.as_ref().unwrap().as_str().as_deref(),
+            Some("b")
+        );
+        assert_eq!(entries[1].1.as_i32(), Some(2));
+        assert!(entries[2].0.is_none());
+        assert_eq!(db.repair_orphans().unwrap(), 0);
+
+        let query = Query::new(Link::target(Data::text("c")));
+        assert_eq!(db.query_ids_filtered(&query).unwrap().len(), 1);
+
+        // Nothing is kept if `f` fails
+        let other: ID = "2".parse().unwrap();
+        let res = db.link_sink(other, |sink| {
+            sink.push_unkeyed(Box::new("d"))?;
+            Err::<(), _>(Error::InvalidQuery("abort".into()))
+        });
+        assert!(res.is_err());
+        assert!(!db.exists(other).unwrap());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn set_page_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(dir.path().join("data.sqlite")).unwrap();
+        db.init().unwrap();
+        let stored = db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+        let page_size = || -> u32 {
+            let conn = db.conn.lock().unwrap();
+            conn.pragma_query_value(None, "page_size", |r| r.get(0))
+                .unwrap()
+        };
+        assert_ne!(page_size(), 8192);
+
+        db.set_page_size(8192).unwrap();
+        assert_eq!(page_size(), 8192);
+        assert_eq!(stored.entries().unwrap().len(), 3);
+
+        for invalid in [0, 256, 1000, 131_072] {
+            let err = db.set_page_size(invalid).unwrap_err();
+            assert!(matches!(err, Error::InvalidPageSize(b) if b == invalid));
+        }
+        assert_eq!(page_size(), 8192);
+
+        db.conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "journal_mode", "WAL")
+            .unwrap();
+        let err = db.set_page_size(4096).unwrap_err();
+        assert!(matches!(err, Error::PageSizeInWal));
+        assert_eq!(page_size(), 8192);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn query_with_values() {
+        use datalink::query::prelude::*;
+
+        crate::test_util::capture_logs();
+        let db = test_db();
+        db.store(&vec!["a", "a", "a", "b"].into_unique_random())
+            .unwrap();
+        let query = Query::new(Link::target(Data::text("a")));
+
+        db.set_trace(true).unwrap();
+        let naive: Vec<_> = db
+            .query_ids_filtered(&query)
+            .unwrap()
+            .into_iter()
+            .map(|id| db.get(id).value().unwrap())
+            .collect();
+        let naive_statements = crate::test_util::take_logs(SQL_LOG_TARGET).len();
+
+        let batched = db.query_with_values(&query).unwrap();
+        let batched_statements = crate::test_util::take_logs(SQL_LOG_TARGET).len();
+        db.set_trace(false).unwrap();
+
+        // Traced and profiled once per statement
+        assert_eq!(naive_statements, 2 * 4);
+        assert_eq!(batched_statements, 2);
+
+        assert_eq!(naive.len(), 3);
+        assert_eq!(batched.len(), 3);
+        for (data, values) in batched {
+            assert_eq!(data.value().unwrap(), values.first().cloned());
+            assert!(naive.contains(&values.into_iter().next()));
+        }
+    }
+
+    #[cfg(not(feature = "random"))]
+    #[test]
+    fn without_random() {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        let [a, b, c] = ["1", "2", "3"].map(|id| id.parse::<ID>().unwrap());
+
+        // Neither a seed nor the `random` feature to mint the items' ids
+        assert!(db.store(&WithId::new(vec![1, 2], a)).is_err());
+        assert!(!db.exists(a).unwrap());
+
+        let stored = db.store(&WithId::new("plain", a)).unwrap();
+        assert_eq!(stored.as_str().unwrap(), "plain");
+
+        let items = Vec::from([WithId::new(3, b), WithId::new(4, c)]);
+        let stored = db.store(&WithId::new(items, a)).unwrap();
+        let ids: Vec<_> = stored
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(_, target)| target.id())
+            .collect();
+        assert_eq!(ids, [b, c]);
+
+        let db = db.with_random_seed(1);
+        let stored = db.store(&WithId::new(vec![5, 6], b)).unwrap();
+        assert_eq!(stored.as_list().unwrap().len(), 2);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn get_or_store() {
+        let db = test_db();
+        let total_changes = || -> i64 {
+            let conn = db.conn.lock().unwrap();
+            conn.query_row("SELECT total_changes()", [], |r| r.get(0))
+                .unwrap()
+        };
+        let data = vec![1, 2].into_unique_random();
+        assert!(!db.exists(data.id()).unwrap());
+
+        let stored = db.get_or_store(&data).unwrap();
+        assert!(db.exists(data.id()).unwrap());
+        let changes = total_changes();
+        assert!(changes > 0);
+
+        let again = db.get_or_store(&data).unwrap();
+        assert_eq!(total_changes(), changes);
+        assert_eq!(stored.id(), again.id());
+        assert_eq!(again.as_list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Database>();
+        assert_send_sync::<StoredData>();
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn shared_across_threads() {
+        let db = test_db();
+        let handles = (0..4)
+            .map(|i| {
+                let db = db.clone();
+                std::thread::spawn(move || db.store(&i.into_unique_random()).unwrap().id())
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let id = handle.join().unwrap();
+            assert!(db.exists(id).unwrap());
+        }
     }
 
+    #[cfg(feature = "random")]
     #[test]
-    #[should_panic]
     fn uninitialized() {
         let db = Database::open_in_memory().unwrap();
-        let _ = db.store(&true.into_unique_random()).unwrap();
+        let err = db.store(&true.into_unique_random()).unwrap_err();
+        assert!(matches!(err, Error::NotInitialized), "{err:?}");
     }
 }
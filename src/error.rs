@@ -1,19 +1,100 @@
 use datalink::links::LinkError;
+use rusqlite::ffi;
+use std::path::PathBuf;
 
+/// Errors of this crate.
+///
+/// New variants may be added, match on [`Error::as_sql`] to handle SQLite errors.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("Invalid query")]
-    InvalidQuery,
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
     #[error("Invalid ID")]
     InvalidID,
+    #[error("Database is already locked by this thread")]
+    Reentrant,
+    #[error("Database is opened read-only")]
+    ReadOnly,
+    #[error("Database is not initialized")]
+    NotInitialized,
+    #[error("Database schema is newer than supported")]
+    SchemaTooNew,
+    #[error("Can't migrate id {id:?}: {reason}")]
+    MigrationData { id: String, reason: String },
+    #[error("Database schema doesn't match version {0}")]
+    SchemaMismatch(i32),
+    #[error("Invalid schema version: {0}")]
+    InvalidVersion(i32),
+    #[error("No database at {0}")]
+    NotFound(PathBuf),
+    #[error("Data belongs to a different database")]
+    ForeignData,
+    #[error("No roots given, refusing to prune everything")]
+    NoRoots,
+    #[error("Pragma not allowed: {0}")]
+    InvalidPragma(String),
+    #[error("Corrupt value in column {column}: expected {expected} bytes, found {found}")]
+    CorruptValue {
+        column: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Invalid page size: {0}")]
+    InvalidPageSize(u32),
+    #[error("Page size can't be changed in WAL mode")]
+    PageSizeInWal,
+    #[error("Data without id, enable the `random` feature to generate one")]
+    MissingId,
+    #[error("Conflicting entry: {0}")]
+    Conflict(rusqlite::Error),
     #[error(transparent)]
     DataLink(#[from] LinkError),
     #[error(transparent)]
-    Sql(#[from] rusqlite::Error),
+    Sql(rusqlite::Error),
     #[error(transparent)]
     FromSql(#[from] rusqlite::types::FromSqlError),
 }
 
+impl From<rusqlite::Error> for Error {
+    #[inline]
+    fn from(value: rusqlite::Error) -> Self {
+        match &value {
+            rusqlite::Error::SqliteFailure(e, _)
+                if matches!(
+                    e.extended_code,
+                    ffi::SQLITE_CONSTRAINT_UNIQUE | ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+                ) =>
+            {
+                Self::Conflict(value)
+            }
+            _ => Self::Sql(value),
+        }
+    }
+}
+
+impl Error {
+    /// The underlying SQLite error, if any.
+    #[inline]
+    #[must_use]
+    pub fn as_sql(&self) -> Option<&rusqlite::Error> {
+        match self {
+            Self::Sql(e) | Self::Conflict(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The extended result code of the underlying SQLite error, like `SQLITE_CONSTRAINT_UNIQUE`.
+    #[inline]
+    #[must_use]
+    pub fn sqlite_extended_code(&self) -> Option<i32> {
+        match self.as_sql()? {
+            rusqlite::Error::SqliteFailure(e, _) => Some(e.extended_code),
+            _ => None,
+        }
+    }
+}
+
 impl From<Error> for LinkError {
     #[inline]
     fn from(value: Error) -> Self {
@@ -25,3 +106,47 @@ impl From<Error> for LinkError {
 }
 
 pub type Result<T = (), E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::Database, util::SqlID};
+    use datalink::id::ID;
+
+    #[test]
+    fn unique_conflict() {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        let id = SqlID::from("1".parse::<ID>().unwrap());
+
+        let conn = db.conn.lock().unwrap();
+        const INSERT: &str = "INSERT INTO `values` (`uuid`) VALUES (?)";
+        conn.execute(INSERT, [id]).unwrap();
+        let err = conn.execute(INSERT, [id]).unwrap_err();
+
+        let err = Error::from(err);
+        assert!(matches!(err, Error::Conflict(_)));
+        assert_eq!(
+            err.as_sql().and_then(rusqlite::Error::sqlite_error_code),
+            Some(rusqlite::ErrorCode::ConstraintViolation)
+        );
+        assert!(matches!(
+            err.sqlite_extended_code(),
+            Some(ffi::SQLITE_CONSTRAINT_UNIQUE | ffi::SQLITE_CONSTRAINT_PRIMARYKEY)
+        ));
+    }
+
+    #[test]
+    fn other_sql_error() {
+        let db = Database::open_in_memory().unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        let err = conn.execute("SELECT * FROM `missing`", []).unwrap_err();
+
+        let err = Error::from(err);
+        assert!(matches!(err, Error::Sql(_)));
+        assert!(err.as_sql().is_some());
+        assert!(Error::NotInitialized.as_sql().is_none());
+        assert_eq!(Error::NotInitialized.sqlite_extended_code(), None);
+    }
+}
@@ -1,10 +1,16 @@
+#[cfg(feature = "tokio")]
+pub mod async_database;
 pub mod database;
 pub mod error;
 #[cfg(feature = "migrations")]
 pub mod migration;
+pub mod primitive;
 mod query;
+pub mod schema;
 pub mod storable;
 pub mod storeddata;
+#[cfg(test)]
+mod test_util;
 pub mod util;
 
 pub use rusqlite;
@@ -12,7 +18,7 @@ pub use rusqlite;
 #[macro_export]
 macro_rules! schema_version {
     () => {
-        2i32
+        3i32
     };
 }
 
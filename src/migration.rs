@@ -1,8 +1,10 @@
 use datalink::id::ID;
 use rusqlite::{params, Connection};
+use std::collections::HashMap;
 
-use crate::database::Database;
+use crate::database::{detect_version, Database};
 use crate::error::{Error, Result};
+use crate::schema::Schema;
 use crate::util::SqlID;
 
 type Version = i32;
@@ -23,7 +25,6 @@ impl<'db> Migrations<'db> {
     #[inline]
     pub fn run_one(&mut self) -> Option<Result<Version>> {
         debug_assert!(self.version >= 0);
-        debug_assert!(self.version <= crate::schema_version!());
         debug_assert_eq!(self.version, self.db.schema_version().unwrap_or(0));
 
         if self.version >= crate::schema_version!() {
@@ -33,8 +34,10 @@ impl<'db> Migrations<'db> {
         macro_rules! migrate_to {
             ($version:literal) => {{
                 log::info!(concat!("Migrating to version ", $version, " ..."));
-                let mut conn = self.db.conn.lock().unwrap();
-                let res = Migration::<$version>::run(&mut conn);
+                let res = self
+                    .db
+                    .lock()
+                    .and_then(|mut conn| Migration::<$version>::run(&mut conn, &self.db.schema));
                 log::info!(concat!("Migrated to version ", $version));
                 res
             }};
@@ -43,6 +46,7 @@ impl<'db> Migrations<'db> {
         let res = match self.version {
             0 => migrate_to!(1),
             1 => migrate_to!(2),
+            2 => migrate_to!(3),
             v => {
                 unreachable!("Unknown version: {v}");
             }
@@ -56,6 +60,39 @@ impl<'db> Migrations<'db> {
         }
     }
 
+    /// Sets the schema version to `v` without migrating,
+    /// to recover from a migration that only partially applied.
+    ///
+    /// Fails with [`Error::SchemaMismatch`] unless the tables have the shape of version `v`.
+    pub fn force_version(&mut self, v: Version) -> Result {
+        let conn = self.db.lock()?;
+        if detect_version(&conn, &self.db.schema)? != Some(v) {
+            return Err(Error::SchemaMismatch(v));
+        }
+        conn.pragma_update(None, "user_version", v)?;
+        log::warn!("Forced schema version {v}");
+        self.version = v;
+        Ok(())
+    }
+
+    /// Runs only the migrations from version `from` to version `to`.
+    ///
+    /// Fails with [`Error::SchemaMismatch`] unless the database is at version `from`
+    /// and with [`Error::InvalidVersion`] if `to` is before `from` or unknown.
+    pub fn run_range(&mut self, from: Version, to: Version) -> Result<()> {
+        if self.version != from {
+            return Err(Error::SchemaMismatch(from));
+        }
+        if !(from..=crate::schema_version!()).contains(&to) {
+            return Err(Error::InvalidVersion(to));
+        }
+
+        while self.version < to {
+            self.run_one().expect("Version checked above")?;
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn run_all(self) -> Result<()> {
         for result in self {
@@ -75,7 +112,8 @@ impl Iterator for Migrations<'_> {
 
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let len = crate::schema_version!() as usize - self.version as usize;
+        // Nothing to run for a newer schema
+        let len = crate::schema_version!().saturating_sub(self.version).max(0) as usize;
         (len, Some(len))
     }
 }
@@ -89,35 +127,70 @@ pub fn migrate(db: &Database) -> Migrations<'_> {
     Migrations::new(db)
 }
 
-struct Migration<const V: i32>;
+/// The migration to schema version `V`.
+pub struct Migration<const V: i32>;
+
+// Fails to compile if `schema_version!()` has no migration leading to it
+const _: fn() -> &'static str = Migration::<{ crate::schema_version!() }>::up_sql;
 
 impl Migration<1> {
-    fn run(conn: &mut Connection) -> Result<()> {
-        conn.execute_batch(include_str!("migrations/1.sql"))?;
+    /// The SQL run by this migration.
+    #[inline]
+    #[must_use]
+    pub const fn up_sql() -> &'static str {
+        include_str!("migrations/1.sql")
+    }
+
+    fn run(conn: &mut Connection, schema: &Schema) -> Result<()> {
+        conn.execute_batch(&schema.render(Self::up_sql()))?;
         Ok(())
     }
 }
 
 impl Migration<2> {
-    fn run(conn: &mut Connection) -> Result<()> {
+    /// The SQL run by this migration.
+    ///
+    /// Between both statements the ids of all rows are converted
+    /// to the new `uuid` columns, which can't be expressed in SQL.
+    #[inline]
+    #[must_use]
+    pub const fn up_sql() -> &'static str {
+        concat!(
+            include_str!("migrations/2a.sql"),
+            "-- Convert ids to uuids\n",
+            include_str!("migrations/2b.sql")
+        )
+    }
+
+    fn run(conn: &mut Connection, schema: &Schema) -> Result<()> {
         let tx = conn.transaction()?;
         {
-            tx.execute_batch(include_str!("migrations/2a.sql"))?;
+            tx.execute_batch(&schema.render(include_str!("migrations/2a.sql")))?;
             // Convert value rows
-            let mut select = tx.prepare("SELECT `id` FROM `values`")?;
-            let mut update = tx.prepare("UPDATE `values` SET `uuid` = ? WHERE `id` = ?")?;
+            let mut select = tx.prepare(&schema.render("SELECT `id` FROM `values`"))?;
+            let mut update =
+                tx.prepare(&schema.render("UPDATE `values` SET `uuid` = ? WHERE `id` = ?"))?;
 
             let mut rows = select.query([])?;
+            // Different strings may parse to the same id
+            let mut seen = HashMap::new();
 
             while let Some(row) = rows.next()? {
                 let id_str: String = row.get(0)?;
-                let id: SqlID = id_str.parse::<ID>().map_err(|_| Error::InvalidID)?.into();
+                let id = parse_v1_id(&id_str)?;
+                if let Some(first) = seen.insert(ID::from(id), id_str.clone()) {
+                    return Err(Error::MigrationData {
+                        id: id_str,
+                        reason: format!("same id as {first:?}"),
+                    });
+                }
                 update.execute(params![id, id_str])?;
             }
             // Convert link rows
-            let mut select =
-                tx.prepare("SELECT `source_id`, `key_id`, `target_id` FROM `links`")?;
-            let mut update = tx.prepare("UPDATE `links` SET `source_uuid` = ?, `key_uuid` = ?, `target_uuid` = ? WHERE `source_id` IS ? AND `key_id` IS ? AND `target_id` IS ?")?;
+            let mut select = tx.prepare(
+                &schema.render("SELECT `source_id`, `key_id`, `target_id` FROM `links`"),
+            )?;
+            let mut update = tx.prepare(&schema.render("UPDATE `links` SET `source_uuid` = ?, `key_uuid` = ?, `target_uuid` = ? WHERE `source_id` IS ? AND `key_id` IS ? AND `target_id` IS ?"))?;
 
             let mut rows = select.query([])?;
 
@@ -125,19 +198,9 @@ impl Migration<2> {
                 let source_id_str: String = row.get(0)?;
                 let key_id_str: Option<String> = row.get(1)?;
                 let target_id_str: String = row.get(2)?;
-                let source_id: SqlID = source_id_str
-                    .parse::<ID>()
-                    .map_err(|_| Error::InvalidID)?
-                    .into();
-                let key_id: Option<SqlID> = key_id_str
-                    .as_ref()
-                    .map(|s| s.parse::<ID>().map_err(|_| Error::InvalidID))
-                    .transpose()?
-                    .map(SqlID::from);
-                let target_id: SqlID = target_id_str
-                    .parse::<ID>()
-                    .map_err(|_| Error::InvalidID)?
-                    .into();
+                let source_id = parse_v1_id(&source_id_str)?;
+                let key_id = key_id_str.as_deref().map(parse_v1_id).transpose()?;
+                let target_id = parse_v1_id(&target_id_str)?;
                 update.execute(params![
                     source_id,
                     key_id,
@@ -147,19 +210,46 @@ impl Migration<2> {
                     target_id_str
                 ])?;
             }
-            tx.execute_batch(include_str!("migrations/2b.sql"))?;
+            tx.execute_batch(&schema.render(include_str!("migrations/2b.sql")))?;
         }
         tx.commit()?;
         Ok(())
     }
 }
 
+/// Parses an id of schema version 1, naming it in the error.
+fn parse_v1_id(id: &str) -> Result<SqlID> {
+    id.parse::<ID>()
+        .map(SqlID::from)
+        .map_err(|_| Error::MigrationData {
+            id: id.to_owned(),
+            reason: "not a valid id".into(),
+        })
+}
+
+impl Migration<3> {
+    /// The SQL run by this migration.
+    #[inline]
+    #[must_use]
+    pub const fn up_sql() -> &'static str {
+        include_str!("migrations/3.sql")
+    }
+
+    fn run(conn: &mut Connection, schema: &Schema) -> Result<()> {
+        let tx = conn.transaction()?;
+        tx.execute_batch(&schema.render(Self::up_sql()))?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use datalink::{data::DataExt, Data};
 
     use super::*;
-    use crate::database::Database;
+    use crate::database::{detect_version, Database};
+    use crate::schema::Schema;
 
     #[test]
     fn all() {
@@ -228,4 +318,199 @@ mod tests {
         assert_eq!(list[0].get_id(), Some("2".parse().unwrap()));
         assert_eq!(list[0].as_bool().unwrap(), true);
     }
+
+    #[test]
+    fn custom_schema() {
+        let schema = Schema {
+            values: "data_values".into(),
+            links: "data_links".into(),
+            uuid: "data_uuid".into(),
+            source_uuid: "data_source".into(),
+            key_uuid: "data_key".into(),
+            target_uuid: "data_target".into(),
+        };
+        let db = Database::open_in_memory()
+            .unwrap()
+            .with_schema(schema.clone());
+        let mut migrations = migrate(&db);
+        migrations.run_range(0, 1).unwrap();
+
+        const INSERTS: &str = r"
+            INSERT INTO `data_values` (`id`, `bool`) VALUES ('1', 1);
+            INSERT INTO `data_values` (`id`, `str`) VALUES ('2', 'key');
+            INSERT INTO `data_links` (`source_id`, `key_id`, `target_id`) VALUES ('1', '2', '1');
+        ";
+        db.conn.lock().unwrap().execute_batch(INSERTS).unwrap();
+        migrations.run_all().unwrap();
+
+        let data = db.get("1".parse().unwrap());
+        assert_eq!(data.as_bool().unwrap(), true);
+        assert_eq!(data.as_items().unwrap().len(), 1);
+
+        let conn = db.conn.lock().unwrap();
+        assert_eq!(detect_version(&conn, &schema).unwrap(), Some(3));
+        assert_eq!(detect_version(&conn, &Schema::default()).unwrap(), Some(0));
+        drop(conn);
+        migrate(&db).force_version(3).unwrap();
+    }
+
+    #[test]
+    fn run_range() {
+        let db = Database::open_in_memory().unwrap();
+        let mut migrations = migrate(&db);
+        migrations.run_range(0, 1).unwrap();
+        assert_eq!(db.schema_version().unwrap(), 1);
+
+        migrations.run_range(1, 2).unwrap();
+        assert_eq!(db.schema_version().unwrap(), 2);
+        let conn = db.conn.lock().unwrap();
+        assert_eq!(detect_version(&conn, &Schema::default()).unwrap(), Some(2));
+        drop(conn);
+
+        migrations.run_range(2, 2).unwrap();
+        assert_eq!(db.schema_version().unwrap(), 2);
+        assert_eq!(migrations.len(), 1);
+
+        let err = migrations.run_range(1, 3).unwrap_err();
+        assert!(matches!(err, Error::SchemaMismatch(1)), "{err:?}");
+        for to in [1, crate::schema_version!() + 1] {
+            let err = migrations.run_range(2, to).unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidVersion(v) if v == to),
+                "{err:?}"
+            );
+        }
+        assert_eq!(db.schema_version().unwrap(), 2);
+    }
+
+    #[test]
+    fn force_version() {
+        let db = Database::open_in_memory().unwrap();
+        let mut migrations = migrate(&db);
+        migrations.next().unwrap().unwrap();
+        migrations.next().unwrap().unwrap();
+
+        // Pretend the version wasn't recorded
+        let conn = db.conn.lock().unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        drop(conn);
+
+        let mut migrations = migrate(&db);
+        for v in [0, 1, 3, 4] {
+            let err = migrations.force_version(v).unwrap_err();
+            assert!(matches!(err, Error::SchemaMismatch(e) if e == v), "{err:?}");
+        }
+        assert_eq!(db.schema_version().unwrap(), 1);
+
+        migrations.force_version(2).unwrap();
+        assert_eq!(db.schema_version().unwrap(), 2);
+        migrations.run_all().unwrap();
+        assert_eq!(db.schema_version().unwrap(), crate::schema_version!());
+    }
+
+    #[test]
+    fn invalid_v1_id() {
+        let db = Database::open_in_memory().unwrap();
+        let mut migrations = migrate(&db);
+        migrations.next().unwrap().unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        conn.execute_batch("INSERT INTO `values` (`id`) VALUES ('1'), ('not a valid id!!');")
+            .unwrap();
+        drop(conn);
+
+        let err = migrations.next().unwrap().unwrap_err();
+        assert!(
+            matches!(&err, Error::MigrationData { id, .. } if id == "not a valid id!!"),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains("\"not a valid id!!\""), "{err}");
+        // Nothing was changed
+        assert_eq!(db.schema_version().unwrap(), 1);
+    }
+
+    #[test]
+    fn duplicate_v1_id() {
+        let one: ID = "1".parse().unwrap();
+        // Another spelling of the same id
+        let alias = match one.to_string() {
+            canonical if canonical != "1" => canonical,
+            _ => "01".to_owned(),
+        };
+        assert_eq!(alias.parse::<ID>().unwrap(), one);
+
+        let db = Database::open_in_memory().unwrap();
+        let mut migrations = migrate(&db);
+        migrations.next().unwrap().unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        conn.execute("INSERT INTO `values` (`id`) VALUES ('1'), (?)", [&alias])
+            .unwrap();
+        drop(conn);
+
+        let err = migrations.next().unwrap().unwrap_err();
+        let Error::MigrationData { id, reason } = &err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(id, &alias);
+        assert!(reason.contains("\"1\""), "{reason}");
+        assert_eq!(db.schema_version().unwrap(), 1);
+    }
+
+    #[test]
+    fn up_sql() {
+        assert!(Migration::<1>::up_sql().contains("CREATE TABLE"));
+        assert!(Migration::<2>::up_sql().contains("`uuid`"));
+        assert!(Migration::<3>::up_sql().contains("`created_at`"));
+    }
+
+    #[test]
+    fn newer_schema() {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        let newer = crate::schema_version!() + 1;
+        db.conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "user_version", newer)
+            .unwrap();
+
+        let mut migrations = migrate(&db);
+        assert_eq!(migrations.len(), 0);
+        assert!(migrations.next().is_none());
+        assert_eq!(db.schema_version().unwrap(), newer);
+    }
+
+    #[test]
+    fn timestamps_backfilled() {
+        let db = Database::open_in_memory().unwrap();
+        let mut migrations = migrate(&db);
+
+        // Migrate only to version 2
+        migrations.next().unwrap().unwrap();
+        let v = migrations.next().unwrap().unwrap();
+        assert_eq!(v, 2);
+
+        let id = SqlID::from("1".parse::<ID>().unwrap());
+        let conn = db.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO `values` (`uuid`, `str`) VALUES (?, 'kept')",
+            [id],
+        )
+        .unwrap();
+        drop(conn);
+
+        migrations.run_all().unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        let (s, created_at): (String, Option<String>) = conn
+            .query_row(
+                "SELECT `str`, `created_at` FROM `values` WHERE `uuid` = ?",
+                [id],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(s, "kept");
+        assert!(created_at.is_some());
+    }
 }
@@ -5,7 +5,7 @@ DROP INDEX IF EXISTS `links_keyed`;
 -- Instructions from https://www.sqlite.org/lang_altertable.html
 PRAGMA foreign_keys = off;
 CREATE TABLE `values_new` (
-    `uuid` BLOB NOT NULL UNIQUE CHECK(length(uuid) = 16),
+    `uuid` BLOB NOT NULL UNIQUE CHECK(length(`uuid`) = 16),
     `bool` BOOLEAN,
     `u8` UNSIGNED INT(1),
     `i8` INT(1),
@@ -39,9 +39,9 @@ DROP TABLE `values`;
 ALTER TABLE `values_new`
     RENAME TO `values`;
 CREATE TABLE `links_new` (
-    `source_uuid` BLOB NOT NULL CHECK(length(source_uuid) = 16),
-    `key_uuid` BLOB CHECK(length(key_uuid) = 16),
-    `target_uuid` BLOB NOT NULL CHECK(length(target_uuid) = 16)
+    `source_uuid` BLOB NOT NULL CHECK(length(`source_uuid`) = 16),
+    `key_uuid` BLOB CHECK(length(`key_uuid`) = 16),
+    `target_uuid` BLOB NOT NULL CHECK(length(`target_uuid`) = 16)
 );
 INSERT INTO `links_new`
 SELECT `source_uuid`,
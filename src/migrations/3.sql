@@ -0,0 +1,39 @@
+ALTER TABLE `values` ADD COLUMN `created_at` TEXT;
+ALTER TABLE `values` ADD COLUMN `updated_at` TEXT;
+ALTER TABLE `links` ADD COLUMN `created_at` TEXT;
+ALTER TABLE `links` ADD COLUMN `updated_at` TEXT;
+-- Existing rows get the time of the migration
+UPDATE `values`
+SET `created_at` = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+    `updated_at` = strftime('%Y-%m-%d %H:%M:%f', 'now');
+UPDATE `links`
+SET `created_at` = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+    `updated_at` = strftime('%Y-%m-%d %H:%M:%f', 'now');
+-- ALTER TABLE can't add columns with a non-constant default
+CREATE TRIGGER `values_created`
+AFTER INSERT ON `values`
+    FOR EACH ROW
+    WHEN NEW.`created_at` IS NULL BEGIN
+UPDATE `values`
+SET `created_at` = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+    `updated_at` = strftime('%Y-%m-%d %H:%M:%f', 'now')
+WHERE `uuid` = NEW.`uuid`;
+END;
+CREATE TRIGGER `values_updated`
+AFTER UPDATE ON `values`
+    FOR EACH ROW
+    WHEN NEW.`updated_at` IS OLD.`updated_at` BEGIN
+UPDATE `values`
+SET `updated_at` = strftime('%Y-%m-%d %H:%M:%f', 'now')
+WHERE `uuid` = NEW.`uuid`;
+END;
+CREATE TRIGGER `links_created`
+AFTER INSERT ON `links`
+    FOR EACH ROW
+    WHEN NEW.`created_at` IS NULL BEGIN
+UPDATE `links`
+SET `created_at` = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+    `updated_at` = strftime('%Y-%m-%d %H:%M:%f', 'now')
+WHERE `rowid` = NEW.`rowid`;
+END;
+PRAGMA user_version = 3;
\ No newline at end of file
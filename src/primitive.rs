@@ -0,0 +1,276 @@
+use datalink::value::{ValueQuery, ValueRequest};
+use rusqlite::{
+    types::{FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Row, ToSql,
+};
+use std::sync::OnceLock;
+
+/// Reads a cell of the `bool` column.
+///
+/// Booleans are stored as the integers `0` and `1`, but databases written by
+/// other tools may contain any integer or the text `true`/`false` instead.
+/// Like in C, every integer but `0` is true.
+pub(crate) fn bool_from_sql(value: ValueRef<'_>) -> Option<bool> {
+    match value {
+        ValueRef::Integer(i) => Some(i != 0),
+        ValueRef::Text(t) if t.eq_ignore_ascii_case(b"true") => Some(true),
+        ValueRef::Text(t) if t.eq_ignore_ascii_case(b"false") => Some(false),
+        _ => None,
+    }
+}
+
+/// Type of a [`Primitive`], each stored in its own column of the `values` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrimitiveType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    Str,
+}
+
+impl PrimitiveType {
+    /// All types in the order of their columns
+    pub const ALL: [PrimitiveType; 12] = [
+        Self::Bool,
+        Self::U8,
+        Self::I8,
+        Self::U16,
+        Self::I16,
+        Self::U32,
+        Self::I32,
+        Self::U64,
+        Self::I64,
+        Self::F32,
+        Self::F64,
+        Self::Str,
+    ];
+
+    #[inline]
+    #[must_use]
+    pub const fn column(self) -> &'static str {
+        match self {
+            Self::Bool => "bool",
+            Self::U8 => "u8",
+            Self::I8 => "i8",
+            Self::U16 => "u16",
+            Self::I16 => "i16",
+            Self::U32 => "u32",
+            Self::I32 => "i32",
+            Self::U64 => "u64",
+            Self::I64 => "i64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::Str => "str",
+        }
+    }
+}
+
+/// Columns of all [`PrimitiveType`]s, in the order of [`PrimitiveType::ALL`]
+pub(crate) fn all_columns() -> &'static str {
+    static COLUMNS: OnceLock<String> = OnceLock::new();
+    COLUMNS.get_or_init(|| {
+        PrimitiveType::ALL
+            .map(|ty| format!("`{}`", ty.column()))
+            .join(", ")
+    })
+}
+
+/// Assignments of all columns of [`all_columns`] from `excluded`,
+/// for the `DO UPDATE SET` of an upsert into the `values` table.
+pub(crate) fn set_all_excluded() -> &'static str {
+    static SET: OnceLock<String> = OnceLock::new();
+    SET.get_or_init(|| {
+        PrimitiveType::ALL
+            .map(|ty| format!("`{0}`=excluded.`{0}`", ty.column()))
+            .join(", ")
+    })
+}
+
+/// A single value as stored in the `values` table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+    Bool(bool),
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Str(String),
+}
+
+impl Primitive {
+    #[inline]
+    #[must_use]
+    pub const fn ty(&self) -> PrimitiveType {
+        match self {
+            Self::Bool(_) => PrimitiveType::Bool,
+            Self::U8(_) => PrimitiveType::U8,
+            Self::I8(_) => PrimitiveType::I8,
+            Self::U16(_) => PrimitiveType::U16,
+            Self::I16(_) => PrimitiveType::I16,
+            Self::U32(_) => PrimitiveType::U32,
+            Self::I32(_) => PrimitiveType::I32,
+            Self::U64(_) => PrimitiveType::U64,
+            Self::I64(_) => PrimitiveType::I64,
+            Self::F32(_) => PrimitiveType::F32,
+            Self::F64(_) => PrimitiveType::F64,
+            Self::Str(_) => PrimitiveType::Str,
+        }
+    }
+
+    /// Reads a value of type `ty` from a cell of its column.
+    ///
+    /// Returns `None` for `NULL`.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn from_sql(ty: PrimitiveType, value: ValueRef<'_>) -> FromSqlResult<Option<Self>> {
+        use PrimitiveType as T;
+        use ValueRef as V;
+
+        let primitive = match (ty, value) {
+            (_, V::Null) => return Ok(None),
+            (T::Bool, v) => Self::Bool(bool_from_sql(v).ok_or(FromSqlError::InvalidType)?),
+            (T::U8, V::Integer(i)) => Self::U8(i as u8),
+            (T::I8, V::Integer(i)) => Self::I8(i as i8),
+            (T::U16, V::Integer(i)) => Self::U16(i as u16),
+            (T::I16, V::Integer(i)) => Self::I16(i as i16),
+            (T::U32, V::Integer(i)) => Self::U32(i as u32),
+            (T::I32, V::Integer(i)) => Self::I32(i as i32),
+            (T::U64, V::Integer(i)) => Self::U64(i as u64),
+            (T::I64, V::Integer(i)) => Self::I64(i),
+            (T::F32, V::Real(f)) => Self::F32(f as f32),
+            (T::F32, V::Integer(i)) => Self::F32(i as f32),
+            (T::F64, V::Real(f)) => Self::F64(f),
+            (T::F64, V::Integer(i)) => Self::F64(i as f64),
+            (T::Str, V::Text(_)) => Self::Str(value.as_str()?.to_owned()),
+            _ => return Err(FromSqlError::InvalidType),
+        };
+        Ok(Some(primitive))
+    }
+
+    /// Provides this value to `request`.
+    #[inline]
+    pub fn provide_to<Q: ValueQuery>(&self, request: &mut ValueRequest<Q>) {
+        match self {
+            Self::Bool(v) => request.provide_bool(*v),
+            Self::U8(v) => request.provide_u8(*v),
+            Self::I8(v) => request.provide_i8(*v),
+            Self::U16(v) => request.provide_u16(*v),
+            Self::I16(v) => request.provide_i16(*v),
+            Self::U32(v) => request.provide_u32(*v),
+            Self::I32(v) => request.provide_i32(*v),
+            Self::U64(v) => request.provide_u64(*v),
+            Self::I64(v) => request.provide_i64(*v),
+            Self::F32(v) => request.provide_f32(*v),
+            Self::F64(v) => request.provide_f64(*v),
+            Self::Str(v) => request.provide_str(v),
+        }
+    }
+
+    /// Reads the non-NULL values of a row containing [`all_columns`],
+    /// starting at column `offset`.
+    pub(crate) fn from_row(row: &Row, offset: usize) -> rusqlite::Result<Vec<Self>> {
+        let mut values = Vec::new();
+        for (idx, ty) in PrimitiveType::ALL.into_iter().enumerate() {
+            let idx = offset + idx;
+            let cell = row.get_ref(idx)?;
+            let value = Self::from_sql(ty, cell).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(idx, cell.data_type(), e.into())
+            })?;
+            values.extend(value);
+        }
+        Ok(values)
+    }
+}
+
+impl ToSql for Primitive {
+    #[inline]
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            // Plain integers, like other tools write booleans
+            Self::Bool(v) => Ok(ToSqlOutput::from(i64::from(*v))),
+            Self::U8(v) => v.to_sql(),
+            Self::I8(v) => v.to_sql(),
+            Self::U16(v) => v.to_sql(),
+            Self::I16(v) => v.to_sql(),
+            Self::U32(v) => v.to_sql(),
+            Self::I32(v) => v.to_sql(),
+            Self::U64(v) => v.to_sql(),
+            Self::I64(v) => v.to_sql(),
+            Self::F32(v) => v.to_sql(),
+            Self::F64(v) => v.to_sql(),
+            Self::Str(v) => v.to_sql(),
+        }
+    }
+}
+
+/// Rust type of a [`Primitive`] variant.
+pub trait PrimitiveValue: Sized {
+    const TYPE: PrimitiveType;
+
+    /// Unwraps the value if `primitive` is of [`Self::TYPE`].
+    fn from_primitive(primitive: Primitive) -> Option<Self>;
+}
+
+macro_rules! impl_from {
+    ($($ty:ty => $variant:ident),*) => {
+        $(
+            impl From<$ty> for Primitive {
+                #[inline]
+                fn from(value: $ty) -> Self {
+                    Self::$variant(value)
+                }
+            }
+
+            impl PrimitiveValue for $ty {
+                const TYPE: PrimitiveType = PrimitiveType::$variant;
+
+                #[inline]
+                fn from_primitive(primitive: Primitive) -> Option<Self> {
+                    match primitive {
+                        Primitive::$variant(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    bool => Bool,
+    u8 => U8,
+    i8 => I8,
+    u16 => U16,
+    i16 => I16,
+    u32 => U32,
+    i32 => I32,
+    u64 => U64,
+    i64 => I64,
+    f32 => F32,
+    f64 => F64,
+    String => Str
+);
+
+impl From<&str> for Primitive {
+    #[inline]
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
@@ -1,17 +1,21 @@
 use std::{
     fmt::{Debug, Display, Write},
     marker::PhantomData,
+    ops::ControlFlow,
 };
 
 use datalink::{
+    id::ID,
     links::prelude::*,
     query::{prelude::Text as TextFilter, DataFilter, LinkFilter, Query},
 };
-use rusqlite::{Row, ToSql};
+use rusqlite::{Params, Row, Rows, ToSql};
 
 use crate::{
     database::Database,
     error::{Error, Result},
+    schema::Schema,
+    util::SqlID,
 };
 
 pub trait Operator {
@@ -35,13 +39,19 @@ impl Operator for Disjunction {
     }
 }
 
-#[derive(Default)]
+/// Default for [`SQLBuilder::max_depth`]
+pub const MAX_DEPTH: usize = 32;
+
 pub struct SQLBuilder<C = (), Op: Operator = Conjunction> {
     context: C,
     select: String,
     from: String,
     wher: String,
+    order: String,
+    limit: Option<u64>,
     params: Vec<Box<dyn ToSql>>,
+    depth: usize,
+    max_depth: usize,
     op: PhantomData<Op>,
 }
 
@@ -49,31 +59,72 @@ impl<C> SQLBuilder<C> {
     #[inline]
     #[must_use]
     pub fn new_conjunct(context: impl Into<C>) -> SQLBuilder<C, Conjunction> {
-        SQLBuilder {
-            context: context.into(),
-            select: String::new(),
-            from: String::new(),
-            wher: String::new(),
-            params: Vec::new(),
-            op: PhantomData,
-        }
+        SQLBuilder::with_context(context.into())
     }
 
     #[inline]
     #[must_use]
     pub fn new_disjunct(context: impl Into<C>) -> SQLBuilder<C, Disjunction> {
+        SQLBuilder::with_context(context.into())
+    }
+}
+
+impl<C: Default, O: Operator> Default for SQLBuilder<C, O> {
+    #[inline]
+    fn default() -> Self {
+        Self::with_context(C::default())
+    }
+}
+
+impl<C, O: Operator> SQLBuilder<C, O> {
+    #[inline]
+    fn with_context(context: C) -> Self {
         SQLBuilder {
-            context: context.into(),
+            context,
             select: String::new(),
             from: String::new(),
             wher: String::new(),
+            order: String::new(),
+            limit: None,
             params: Vec::new(),
+            depth: 0,
+            max_depth: MAX_DEPTH,
             op: PhantomData,
         }
     }
-}
 
-impl<C, O: Operator> SQLBuilder<C, O> {
+    /// Limits how deep filters may be nested, [`MAX_DEPTH`] by default.
+    ///
+    /// Building a deeper filter fails with [`Error::InvalidQuery`]
+    /// instead of generating huge SQL or overflowing the stack.
+    #[inline]
+    pub fn max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Makes `inner` one level deeper than this builder.
+    #[inline]
+    pub fn nest<C2, O2: Operator>(
+        &self,
+        mut inner: SQLBuilder<C2, O2>,
+    ) -> Result<SQLBuilder<C2, O2>> {
+        inner.depth = self.depth + 1;
+        inner.max_depth = self.max_depth;
+        inner.check_depth()?;
+        Ok(inner)
+    }
+
+    #[inline]
+    fn check_depth(&self) -> Result {
+        if self.depth > self.max_depth {
+            return Err(Error::InvalidQuery(format!(
+                "filter nested deeper than {}",
+                self.max_depth
+            )));
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn context(&self) -> &C {
         &self.context
@@ -115,12 +166,54 @@ impl<C, O: Operator> SQLBuilder<C, O> {
         self.wher.push_str(wher.as_ref());
     }
     #[inline]
+    pub fn order_by(&mut self, order: impl AsRef<str>) {
+        if order.as_ref().is_empty() {
+            return;
+        }
+        if !self.order.is_empty() {
+            self.order.push_str(", ");
+        }
+        self.order.push_str(order.as_ref());
+    }
+    #[inline]
+    pub fn limit(&mut self, limit: u64) {
+        self.limit = Some(limit);
+    }
+    /// Adds a `<col> IN (?, ...)` condition matching any of `ids`.
+    ///
+    /// An empty set of ids matches nothing.
+    #[inline]
+    pub fn wher_id_in(&mut self, col: impl AsRef<str>, ids: impl IntoIterator<Item = ID>) {
+        let mut placeholders = String::new();
+        for id in ids {
+            if !placeholders.is_empty() {
+                placeholders.push_str(", ");
+            }
+            placeholders.push('?');
+            self.with(SqlID::from(id));
+        }
+        if placeholders.is_empty() {
+            self.wher("0");
+        } else {
+            self.wher(format!("{} IN ({placeholders})", col.as_ref()));
+        }
+    }
+    #[inline]
     pub fn with(&mut self, param: (impl ToSql + 'static)) {
         self.params.push(Box::new(param));
     }
 
+    /// Merges `other` into this builder.
+    ///
+    /// The `ORDER BY` terms of `other` are appended after the ones already
+    /// present, so they only break ties of the outer ordering.
+    /// A `LIMIT` of `other` can't be hoisted without changing the meaning of
+    /// the outer statement, so it is rejected with [`Error::InvalidQuery`].
     #[inline]
-    pub fn extend<C2, O2: Operator>(&mut self, other: SQLBuilder<C2, O2>) {
+    pub fn extend<C2, O2: Operator>(&mut self, other: SQLBuilder<C2, O2>) -> Result {
+        if other.limit.is_some() {
+            return Err(Error::InvalidQuery("LIMIT in a nested query".into()));
+        }
         self.select(&other.select);
         self.from(&other.from);
         if !other.wher.is_empty() {
@@ -128,15 +221,30 @@ impl<C, O: Operator> SQLBuilder<C, O> {
             self.wher.push_str(&other.wher);
             self.wher.push(')');
         }
+        self.order_by(&other.order);
         self.params.extend(other.params);
+        Ok(())
+    }
+
+    /// Runs `EXPLAIN QUERY PLAN` for the generated statement, rendered with `schema`
+    /// like [`SQLBuilder::prepare_cached`] does, and returns the detail column of each plan row.
+    #[inline]
+    pub fn explain(&self, conn: &rusqlite::Connection, schema: &Schema) -> Result<Vec<String>> {
+        let sql = format!("EXPLAIN QUERY PLAN {self}");
+        let mut stmt = conn.prepare(&schema.render(&sql))?;
+        let plan = stmt
+            .query_map(self.params(), |r| r.get(3))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(plan)
     }
 
     #[inline]
     pub fn prepare_cached<'conn>(
         &self,
         conn: &'conn rusqlite::Connection,
+        schema: &Schema,
     ) -> rusqlite::Result<rusqlite::CachedStatement<'conn>> {
-        conn.prepare_cached(&self.to_string())
+        conn.prepare_cached(&schema.render(&self.to_string()))
     }
 }
 
@@ -157,6 +265,13 @@ impl<C, O: Operator> Display for SQLBuilder<C, O> {
             f.write_str(" WHERE ")?;
             f.write_str(&self.wher)?;
         }
+        if !self.order.is_empty() {
+            f.write_str(" ORDER BY ")?;
+            f.write_str(&self.order)?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {limit}")?;
+        }
         Ok(())
     }
 }
@@ -168,6 +283,8 @@ impl<C: Debug, O: Operator> Debug for SQLBuilder<C, O> {
         s.field("select", &self.select);
         s.field("from", &self.from);
         s.field("where", &self.wher);
+        s.field("order", &self.order);
+        s.field("limit", &self.limit);
         s.field("operator", &O::op());
         s.field("context", &self.context);
         let param_cnt = self.params.len();
@@ -188,6 +305,37 @@ pub struct QueryContext {
     pub target_col: String,
 }
 
+impl QueryContext {
+    /// Matching the links of the `links` table, the same as [`QueryContext::default`].
+    #[inline]
+    #[must_use]
+    pub fn links() -> Self {
+        Self {
+            table: "links".into(),
+            key_col: "key_uuid".into(),
+            target_col: "target_uuid".into(),
+        }
+    }
+
+    /// Matching rows of the `values` table by their id, as used by [`Database::prepare_query`].
+    #[inline]
+    #[must_use]
+    pub fn values() -> Self {
+        Self {
+            table: "values".into(),
+            key_col: "uuid".into(),
+            target_col: "uuid".into(),
+        }
+    }
+}
+
+impl Default for QueryContext {
+    #[inline]
+    fn default() -> Self {
+        Self::links()
+    }
+}
+
 impl SqlFragment for Query {
     type Context = QueryContext;
 
@@ -203,12 +351,14 @@ impl SqlFragment for Query {
         sql.select(format!("`{table}`.`{key_col}` as `{key}`"));
         sql.select(format!("`{table}`.`{target_col}` as `{target}`"));
         sql.from(format!("`{table}`"));
-        let mut selector_sql = SQLBuilder::new_conjunct(LinkContext {
-            key_col,
-            target_col,
-        });
+        // Filters refer to the aliases, the plain column names would be
+        // ambiguous inside of their subqueries
+        let mut selector_sql = sql.nest(SQLBuilder::new_conjunct(LinkContext {
+            key_col: key,
+            target_col: target,
+        }))?;
         self.filter().build_sql(&mut selector_sql)?;
-        sql.extend(selector_sql);
+        sql.extend(selector_sql)?;
         Ok(())
     }
 }
@@ -219,6 +369,17 @@ pub struct LinkContext {
     pub target_col: String,
 }
 
+impl Default for LinkContext {
+    /// The key and target columns of the `links` table
+    #[inline]
+    fn default() -> Self {
+        Self {
+            key_col: "key_uuid".into(),
+            target_col: "target_uuid".into(),
+        }
+    }
+}
+
 impl SqlFragment for LinkFilter {
     type Context = LinkContext;
 
@@ -229,37 +390,62 @@ impl SqlFragment for LinkFilter {
             E::Any => sql.wher("1"),
             E::None => sql.wher("0"),
             E::Key(s) => {
-                let mut inner_sql = SQLBuilder::new_conjunct(Column {
+                let mut inner_sql = sql.nest(SQLBuilder::new_conjunct(Column {
                     col: sql.context().key_col.to_owned(),
-                });
+                }))?;
                 s.build_sql(&mut inner_sql)?;
-                sql.extend(inner_sql);
+                sql.extend(inner_sql)?;
             }
             E::Target(s) => {
-                let mut inner_sql = SQLBuilder::new_conjunct(Column {
+                let mut inner_sql = sql.nest(SQLBuilder::new_conjunct(Column {
                     col: sql.context().target_col.to_owned(),
-                });
+                }))?;
                 s.build_sql(&mut inner_sql)?;
-                sql.extend(inner_sql);
+                sql.extend(inner_sql)?;
             }
             E::And(and) => {
+                // A single `None` can't be satisfied, skip building the rest
+                if and.iter().any(|s| {
+                    let s: &LinkFilter = s;
+                    matches!(s, E::None)
+                }) {
+                    sql.wher("0");
+                    return Ok(());
+                }
+                // Built into the same statement, but still a level of recursion
+                sql.depth += 1;
+                sql.check_depth()?;
                 for s in and.iter() {
                     s.build_sql(sql)?;
                 }
+                sql.depth -= 1;
             }
             E::Or(or) => {
-                let mut inner_sql = SQLBuilder::new_disjunct(sql.context().to_owned());
+                // A single `Any` is always satisfied
+                if or.iter().any(|s| {
+                    let s: &LinkFilter = s;
+                    matches!(s, E::Any)
+                }) {
+                    sql.wher("1");
+                    return Ok(());
+                }
+                let mut inner_sql = sql.nest(SQLBuilder::new_disjunct(sql.context().to_owned()))?;
                 for s in or.iter() {
                     s.build_sql(&mut inner_sql)?;
                 }
-                sql.extend(inner_sql);
+                sql.extend(inner_sql)?;
             }
-            _ => return Err(Error::InvalidQuery),
+            _ => return Err(unsupported("link filter", self)),
         }
         Ok(())
     }
 }
 
+/// Error for a `filter` of `kind` that can't be translated to SQL, naming the filter
+fn unsupported(kind: &str, filter: &impl Debug) -> Error {
+    Error::InvalidQuery(format!("unsupported {kind} {filter:?}"))
+}
+
 #[derive(Debug, Clone)]
 pub struct Column {
     pub col: String,
@@ -275,36 +461,78 @@ impl SqlFragment for DataFilter {
             E::None => sql.wher("0"),
             E::Id(id) => {
                 sql.wher(format!("`{}` == ?", sql.context().col));
-                sql.with(id.to_string());
+                sql.with(SqlID::from(*id));
             }
             // Stored Data is always unique
             E::Unique => sql.wher("1"),
             E::NotId(id) => {
                 sql.wher(format!("`{}` != ?", sql.context().col));
-                sql.with(id.to_string());
+                sql.with(SqlID::from(*id));
             }
             E::Not(s) => {
-                let mut inner_sql = SQLBuilder::new_conjunct(sql.context().to_owned());
+                let inner: &DataFilter = &s.0;
+                if let E::Text(text) = inner {
+                    return build_text_exists(text, sql, true);
+                }
 
-                s.0.build_sql(&mut inner_sql)?;
-                sql.select(&inner_sql.select);
-                sql.from(&inner_sql.from);
+                let mut inner_sql = sql.nest(SQLBuilder::new_conjunct(sql.context().to_owned()))?;
+                inner.build_sql(&mut inner_sql)?;
+                // Data filters only produce self-contained conditions,
+                // hoisting their tables would change the outer query.
+                debug_assert!(inner_sql.select.is_empty());
+                debug_assert!(inner_sql.from.is_empty());
                 if !inner_sql.wher.is_empty() {
                     sql.wher(format!("NOT ({})", inner_sql.wher));
                 }
                 sql.params.extend(inner_sql.params);
             }
             E::And(and) => {
+                // A single `None` can't be satisfied, skip building the rest
+                if and.iter().any(|s| {
+                    let s: &DataFilter = s;
+                    matches!(s, E::None)
+                }) {
+                    sql.wher("0");
+                    return Ok(());
+                }
+                // Built into the same statement, but still a level of recursion
+                sql.depth += 1;
+                sql.check_depth()?;
                 for s in and.iter() {
                     s.build_sql(sql)?;
                 }
+                sql.depth -= 1;
             }
             E::Or(or) => {
-                let mut inner_sql = SQLBuilder::new_disjunct(sql.context().to_owned());
+                // A single `Any` is always satisfied
+                if or.iter().any(|s| {
+                    let s: &DataFilter = s;
+                    matches!(s, E::Any)
+                }) {
+                    sql.wher("1");
+                    return Ok(());
+                }
+                let ids = or
+                    .iter()
+                    .map(|s| {
+                        let s: &DataFilter = s;
+                        match s {
+                            E::Id(id) => Some(*id),
+                            _ => None,
+                        }
+                    })
+                    .collect::<Option<Vec<_>>>();
+                if let Some(ids) = ids.filter(|ids| !ids.is_empty()) {
+                    let col = format!("`{}`", sql.context().col);
+                    sql.wher_id_in(col, ids);
+                    return Ok(());
+                }
+
+                let mut inner_sql = sql.nest(SQLBuilder::new_disjunct(sql.context().to_owned()))?;
                 for s in or.iter() {
                     s.build_sql(&mut inner_sql)?;
                 }
-                sql.extend(inner_sql);
+                sql.extend(inner_sql)?;
             }
             E::Text(s) => {
                 s.build_sql(sql)?;
@@ -313,10 +541,11 @@ impl SqlFragment for DataFilter {
                 let tbl = format!("{}_l", sql.context().col.replace('.', "_"));
                 let key_col = format!("{tbl}_k");
                 let target_col = format!("{tbl}_t");
-                let mut inner_sql = SQLBuilder::<LinkContext>::new_conjunct(LinkContext {
-                    key_col: key_col.to_owned(),
-                    target_col: target_col.to_owned(),
-                });
+                let mut inner_sql =
+                    sql.nest(SQLBuilder::<LinkContext>::new_conjunct(LinkContext {
+                        key_col: key_col.to_owned(),
+                        target_col: target_col.to_owned(),
+                    }))?;
                 inner_sql.select(format!("`{tbl}`.`key_uuid` as `{key_col}`"));
                 inner_sql.select(format!("`{tbl}`.`target_uuid` as `{target_col}`"));
                 inner_sql.from(format!("`links` as `{tbl}`"));
@@ -326,7 +555,7 @@ impl SqlFragment for DataFilter {
                 sql.wher(format!("EXISTS ({inner_sql})"));
                 sql.params.extend(inner_sql.params);
             }
-            _ => return Err(Error::InvalidQuery),
+            _ => return Err(unsupported("data filter", self)),
         }
         Ok(())
     }
@@ -336,24 +565,41 @@ impl SqlFragment for TextFilter {
     type Context = Column;
     #[inline]
     fn build_sql(&self, sql: &mut SQLBuilder<Self::Context, impl Operator>) -> Result {
-        let tbl = format!("{}_v", sql.context().col.replace('.', "_"));
-        let mut inner_sql = SQLBuilder::<Column>::new_conjunct(sql.context().to_owned());
-        inner_sql.from(format!("`values` as `{tbl}`"));
-        inner_sql.wher(format!("`{tbl}`.`uuid` == `{}`", sql.context().col));
-
-        {
-            if let Some(search) = self.exact() {
-                inner_sql.wher(format!("`{tbl}`.`str` LIKE ?"));
-                inner_sql.with(search.to_owned());
-            } else {
-                return Err(Error::InvalidQuery);
-            }
+        build_text_exists(self, sql, false)
+    }
+}
+
+#[inline]
+fn build_text_exists(
+    text: &TextFilter,
+    sql: &mut SQLBuilder<Column, impl Operator>,
+    negated: bool,
+) -> Result {
+    let tbl = format!("{}_v", sql.context().col.replace('.', "_"));
+    let mut inner_sql = sql.nest(SQLBuilder::<Column>::new_conjunct(sql.context().to_owned()))?;
+    inner_sql.from(format!("`values` as `{tbl}`"));
+    inner_sql.wher(format!("`{tbl}`.`uuid` == `{}`", sql.context().col));
+
+    // Text is compared exactly, "%" matches any string and "" only the empty one,
+    // but none of them a missing string.
+    // Negated they match data without the text, without a string and without an empty one.
+    match text.exact() {
+        Some("%") => inner_sql.wher(format!("`{tbl}`.`str` IS NOT NULL")),
+        Some("") => inner_sql.wher(format!("`{tbl}`.`str` = ''")),
+        Some(search) => {
+            inner_sql.wher(format!("`{tbl}`.`str` = ?"));
+            inner_sql.with(search.to_owned());
         }
+        None => return Err(unsupported("text filter", text)),
+    }
 
+    if negated {
+        sql.wher(format!("NOT EXISTS ({inner_sql})"));
+    } else {
         sql.wher(format!("EXISTS ({inner_sql})"));
-        sql.params.extend(inner_sql.params);
-        Ok(())
     }
+    sql.params.extend(inner_sql.params);
+    Ok(())
 }
 
 #[inline]
@@ -363,23 +609,65 @@ pub fn build_links<L, C: Debug>(
     links: &mut (impl Links + ?Sized),
     f: impl Fn(&Row) -> Result<L>,
 ) -> Result
+where
+    L: Link,
+    L::Key: Sized + 'static,
+    L::Target: Sized + 'static,
+{
+    build_links_with(db, sql, sql.params(), links, f)
+}
+
+/// Like [`build_links`], binding `params` instead of the ones collected by `sql`.
+///
+/// The statement is cached, so running the same `sql` with different `params`
+/// only compiles it once.
+#[inline]
+pub fn build_links_with<L, C: Debug>(
+    db: &Database,
+    sql: &SQLBuilder<C>,
+    params: impl Params,
+    links: &mut (impl Links + ?Sized),
+    f: impl Fn(&Row) -> Result<L>,
+) -> Result
 where
     L: Link,
     L::Key: Sized + 'static,
     L::Target: Sized + 'static,
 {
     log::trace!("Building links from: {:?}", &sql);
-    let conn = db.conn.lock().unwrap();
+    for_each_row(db, sql, params, f, |link| {
+        if link.build_into(links)?.is_break() {
+            Ok(ControlFlow::Break(()))
+        } else {
+            Ok(ControlFlow::Continue(()))
+        }
+    })
+}
+
+/// Runs `sql` and pushes the result of `f` for every row into `sink`,
+/// until `sink` breaks or the rows are exhausted.
+///
+/// The connection stays locked while `sink` runs,
+/// so it can't use `db` without failing with [`Error::Reentrant`].
+#[inline]
+pub fn for_each_row<T, C: Debug>(
+    db: &Database,
+    sql: &SQLBuilder<C>,
+    params: impl Params,
+    f: impl Fn(&Row) -> Result<T>,
+    mut sink: impl FnMut(T) -> Result<ControlFlow<()>>,
+) -> Result {
+    let conn = db.lock()?;
 
-    let mut stmt = sql.prepare_cached(&conn)?;
+    let mut stmt = sql.prepare_cached(&conn, &db.schema)?;
 
-    let mut rows = stmt.query(sql.params())?;
+    let mut rows = stmt.query(params)?;
 
     loop {
         match rows.next()? {
             None => break Ok(()),
             Some(r) => {
-                if f(r)?.build_into(links)?.is_break() {
+                if sink(f(r)?)?.is_break() {
                     break Ok(());
                 }
             }
@@ -387,12 +675,127 @@ where
     }
 }
 
+/// Runs `sql` and passes a [`QueryCursor`] reading its rows on demand to `g`.
+///
+/// The connection stays locked until `g` returns, which can stop reading at any time.
+#[inline]
+pub fn with_cursor<T, C: Debug, R>(
+    db: &Database,
+    sql: &SQLBuilder<C>,
+    params: impl Params,
+    f: impl Fn(&Row) -> Result<T>,
+    g: impl FnOnce(QueryCursor<'_, T>) -> R,
+) -> Result<R> {
+    let conn = db.lock()?;
+
+    let mut stmt = sql.prepare_cached(&conn, &db.schema)?;
+
+    let rows = stmt.query(params)?;
+
+    Ok(g(QueryCursor {
+        rows,
+        f: &f,
+        done: false,
+    }))
+}
+
+/// Iterator reading the rows of a statement only when asked for,
+/// see [`with_cursor`].
+pub struct QueryCursor<'stmt, T> {
+    rows: Rows<'stmt>,
+    f: &'stmt dyn Fn(&Row) -> Result<T>,
+    done: bool,
+}
+
+impl<T> Iterator for QueryCursor<'_, T> {
+    type Item = Result<T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.rows.next() {
+            Ok(Some(r)) => Some((self.f)(r)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+impl<T> std::iter::FusedIterator for QueryCursor<'_, T> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storeddata::StoredData;
+    use datalink::{data::DataExt, links::prelude::Result as LResult, prelude::Unique};
 
-    #[test]
-    fn complex() {
+    #[derive(Debug)]
+    struct Keyed;
+
+    #[derive(Debug, Default)]
+    struct Counter(usize);
+
+    impl Links for Counter {
+        fn push_unkeyed(&mut self, _target: BoxedData) -> LResult {
+            self.0 += 1;
+            CONTINUE
+        }
+
+        fn push_keyed(&mut self, _target: BoxedData, _key: BoxedData) -> LResult {
+            self.0 += 1;
+            CONTINUE
+        }
+
+        fn push(&mut self, _target: BoxedData, _key: Option<BoxedData>) -> LResult {
+            self.0 += 1;
+            CONTINUE
+        }
+    }
+
+    impl datalink::Data for Keyed {
+        fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+            links.push_link(("a", 1))?;
+            links.push_link(("b", 2))?;
+            Ok(())
+        }
+    }
+
+    fn test_db() -> Database {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        // Linked data without an id needs some source of ids
+        #[cfg(not(feature = "random"))]
+        let db = db.with_random_seed(0);
+        db
+    }
+
+    #[cfg(feature = "random")]
+    fn count_links(stored: &StoredData, query: &Query) -> usize {
+        let mut sql = SQLBuilder::new_conjunct(QueryContext::links());
+        sql.select("`links`.`target_uuid`");
+        sql.wher("`links`.`source_uuid` == ?");
+        sql.with(SqlID::from(stored.id()));
+        query.build_sql(&mut sql).unwrap();
+
+        let conn = stored.db.conn.lock().unwrap();
+        let mut stmt = sql.prepare_cached(&conn, &Schema::default()).unwrap();
+        let mut rows = stmt.query(sql.params()).unwrap();
+        let mut count = 0;
+        while rows.next().unwrap().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    fn complex_sql() -> SQLBuilder<QueryContext> {
         use datalink::query::prelude::*;
 
         let query = Query::new(
@@ -401,12 +804,14 @@ mod tests {
         );
         dbg!(&query);
 
-        let mut sql = SQLBuilder::new_conjunct(QueryContext {
-            table: "links".into(),
-            key_col: "key_uuid".into(),
-            target_col: "target_uuid".into(),
-        });
+        let mut sql = SQLBuilder::new_conjunct(QueryContext::links());
         query.build_sql(&mut sql).unwrap();
+        sql
+    }
+
+    #[test]
+    fn complex() {
+        let sql = complex_sql();
 
         dbg!(&sql);
 
@@ -416,4 +821,363 @@ mod tests {
 
         // assert!(false)
     }
+
+    #[test]
+    fn default_contexts() {
+        use datalink::query::prelude::*;
+
+        let context = QueryContext::default();
+        assert_eq!(context.table, "links");
+        assert_eq!(context.key_col, "key_uuid");
+        assert_eq!(context.target_col, "target_uuid");
+        let context = QueryContext::values();
+        assert_eq!(context.table, "values");
+        assert_eq!(context.key_col, "uuid");
+        assert_eq!(context.target_col, "uuid");
+        let context = LinkContext::default();
+        assert_eq!(context.key_col, "key_uuid");
+        assert_eq!(context.target_col, "target_uuid");
+
+        let mut sql = SQLBuilder::new_conjunct(QueryContext::links());
+        Query::new(Link::key(Data::text("foo")))
+            .build_sql(&mut sql)
+            .unwrap();
+        let sql = sql.to_string();
+        assert!(sql.contains("`links`.`key_uuid` as `links_k`"), "{sql}");
+        assert!(sql.contains("FROM `links`"), "{sql}");
+    }
+
+    #[test]
+    fn explain() {
+        let db = test_db();
+        let sql = complex_sql();
+
+        let conn = db.conn.lock().unwrap();
+        let plan = sql.explain(&conn, &db.schema).unwrap();
+        assert!(plan.iter().any(|row| row.contains("links")), "{plan:?}");
+        drop(conn);
+
+        let schema = Schema {
+            links: "data_links".into(),
+            ..Schema::default()
+        };
+        let db = Database::open_in_memory().unwrap().with_schema(schema);
+        db.init().unwrap();
+        let conn = db.conn.lock().unwrap();
+        let plan = sql.explain(&conn, &db.schema).unwrap();
+        assert!(
+            plan.iter().any(|row| row.contains("data_links")),
+            "{plan:?}"
+        );
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn text_not_equal() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        let data = vec!["foo", "bar", "baz"].into_unique_random();
+        let stored = db.store(&data).unwrap();
+
+        let query = Query::new(Link::target(Data::text("bar")));
+        assert_eq!(count_links(&stored, &query), 1);
+
+        let query = Query::new(Link::target(!Data::text("bar")));
+        assert_eq!(count_links(&stored, &query), 2);
+
+        let query = Query::new(Link::target(!Data::text("qux")));
+        assert_eq!(count_links(&stored, &query), 3);
+    }
+
+    #[test]
+    fn text_null_and_empty() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        let empty: ID = "1".parse().unwrap();
+        let missing: ID = "2".parse().unwrap();
+        let conn = db.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO `values` (`uuid`, `str`) VALUES (?, '')",
+            [SqlID::from(empty)],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO `values` (`uuid`, `bool`) VALUES (?, 1)",
+            [SqlID::from(missing)],
+        )
+        .unwrap();
+        drop(conn);
+
+        let ids = |query: Query| db.query_ids_filtered(&query).unwrap();
+        assert_eq!(ids(Query::new(Link::target(Data::text("%")))), [empty]);
+        assert_eq!(ids(Query::new(Link::target(Data::text("")))), [empty]);
+        assert_eq!(ids(Query::new(Link::target(!Data::text("%")))), [missing]);
+        assert_eq!(ids(Query::new(Link::target(!Data::text("")))), [missing]);
+    }
+
+    #[test]
+    fn text_exact() {
+        use datalink::query::prelude::*;
+
+        let db = test_db();
+        let [lower, upper, percent] = ["1", "2", "3"].map(|id| id.parse::<ID>().unwrap());
+        let conn = db.conn.lock().unwrap();
+        for (id, text) in [(lower, "abc"), (upper, "ABC"), (percent, "a%c")] {
+            conn.execute(
+                "INSERT INTO `values` (`uuid`, `str`) VALUES (?, ?)",
+                rusqlite::params![SqlID::from(id), text],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let ids = |query: Query| db.query_ids_filtered(&query).unwrap();
+        assert_eq!(ids(Query::new(Link::target(Data::text("abc")))), [lower]);
+        assert_eq!(ids(Query::new(Link::target(Data::text("ABC")))), [upper]);
+        assert_eq!(ids(Query::new(Link::target(Data::text("a%c")))), [percent]);
+        assert!(ids(Query::new(Link::target(Data::text("a_c")))).is_empty());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn build_links_with() {
+        let db = test_db();
+        let two = db.store(&vec![1, 2].into_unique_random()).unwrap();
+        let three = db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+
+        let mut sql = SQLBuilder::new_conjunct(());
+        sql.select("`links`.`target_uuid`");
+        sql.from("`links`");
+        sql.wher("`links`.`source_uuid` == ?");
+        let target = |r: &Row| -> Result<StoredData> { Ok(db.get(r.get::<_, SqlID>(0)?.into())) };
+
+        for (data, expected) in [(&two, 2), (&three, 3), (&two, 2)] {
+            let mut counter = Counter::default();
+            let params = [SqlID::from(data.id())];
+            super::build_links_with(&db, &sql, params, &mut counter, target).unwrap();
+            assert_eq!(counter.0, expected);
+        }
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn for_each_row() {
+        let db = test_db();
+        let list = db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+
+        let mut sql = SQLBuilder::new_conjunct(());
+        sql.select("`links`.`target_uuid`");
+        sql.from("`links`");
+        sql.wher("`links`.`source_uuid` == ?");
+        let params = [SqlID::from(list.id())];
+        let target = |r: &Row| -> Result<ID> { Ok(r.get::<_, SqlID>(0)?.into()) };
+
+        let mut received = Vec::new();
+        super::for_each_row(&db, &sql, params, target, |id| {
+            received.push(id);
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        let expected: Vec<ID> = list
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|(_, t)| t.id())
+            .collect();
+        assert_eq!(received, expected);
+
+        // Stops as soon as the sink breaks
+        let mut received = Vec::new();
+        super::for_each_row(&db, &sql, params, target, |id| {
+            received.push(id);
+            Ok(ControlFlow::Break(()))
+        })
+        .unwrap();
+        assert_eq!(received, expected[..1]);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn with_cursor() {
+        let db = test_db();
+        let list = db
+            .store(&(0..100).collect::<Vec<i32>>().into_unique_random())
+            .unwrap();
+
+        let mut sql = SQLBuilder::new_conjunct(());
+        sql.select("`links`.`target_uuid`");
+        sql.from("`links`");
+        sql.wher("`links`.`source_uuid` == ?");
+        let params = [SqlID::from(list.id())];
+        let target = |r: &Row| -> Result<ID> { Ok(r.get::<_, SqlID>(0)?.into()) };
+
+        let first = super::with_cursor(&db, &sql, params, target, |mut cursor| {
+            let first = [cursor.next(), cursor.next()];
+            // Dropped with 98 rows left
+            first.map(|id| id.unwrap().unwrap())
+        })
+        .unwrap();
+        let entries = list.entries().unwrap();
+        assert_eq!(first, [entries[0].1.id(), entries[1].1.id()]);
+
+        let count = super::with_cursor(&db, &sql, params, target, |cursor| cursor.count()).unwrap();
+        assert_eq!(count, 100);
+    }
+
+    #[test]
+    fn short_circuit() {
+        use datalink::query::prelude::*;
+
+        let build_data = |filter: DataFilter| {
+            let mut sql = SQLBuilder::new_conjunct(Column { col: "uuid".into() });
+            filter.build_sql(&mut sql).unwrap();
+            assert!(sql.params.is_empty());
+            sql.to_string()
+        };
+        assert_eq!(
+            build_data(Data::text("a") & DataFilter::None & Data::text("b")),
+            "SELECT 1 WHERE 0"
+        );
+        assert_eq!(
+            build_data(Data::text("a") | DataFilter::Any),
+            "SELECT 1 WHERE 1"
+        );
+
+        let build_link = |filter: LinkFilter| {
+            let mut sql = SQLBuilder::new_conjunct(LinkContext::default());
+            filter.build_sql(&mut sql).unwrap();
+            assert!(sql.params.is_empty());
+            sql.to_string()
+        };
+        assert_eq!(
+            build_link(Link::key(Data::text("a")) & LinkFilter::None),
+            "SELECT 1 WHERE 0"
+        );
+        assert_eq!(
+            build_link(LinkFilter::Any | Link::target(Data::text("a"))),
+            "SELECT 1 WHERE 1"
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        use datalink::query::prelude::*;
+
+        let nested = |depth: usize| {
+            let mut filter: DataFilter = Data::text("a").into();
+            for _ in 0..depth {
+                filter = Data::linked(Link::target(filter)).into();
+            }
+            filter
+        };
+        let build = |filter: DataFilter| {
+            let mut sql = SQLBuilder::new_conjunct(Column { col: "uuid".into() });
+            sql.max_depth(8);
+            filter.build_sql(&mut sql)
+        };
+
+        // Every `linked` nests two builders, one for the link and one for its target
+        build(nested(2)).unwrap();
+        let err = build(nested(8)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid query: filter nested deeper than 8"
+        );
+
+        let mut sql = SQLBuilder::new_conjunct(Column { col: "uuid".into() });
+        nested(MAX_DEPTH).build_sql(&mut sql).unwrap_err();
+    }
+
+    #[test]
+    fn extend_order_and_limit() {
+        let mut outer = SQLBuilder::<()>::new_conjunct(());
+        outer.from("`values`");
+        outer.order_by("`values`.`uuid`");
+
+        let mut inner = SQLBuilder::<()>::new_conjunct(());
+        inner.wher("`values`.`str` IS NOT NULL");
+        inner.order_by("`values`.`str`");
+        outer.extend(inner).unwrap();
+        assert_eq!(
+            outer.to_string(),
+            "SELECT 1 FROM `values` WHERE (`values`.`str` IS NOT NULL) ORDER BY `values`.`uuid`, `values`.`str`"
+        );
+
+        let mut limited = SQLBuilder::<()>::new_conjunct(());
+        limited.limit(1);
+        let err = outer.extend(limited).unwrap_err();
+        assert!(matches!(err, Error::InvalidQuery(_)));
+        assert_eq!(err.to_string(), "Invalid query: LIMIT in a nested query");
+    }
+
+    #[test]
+    fn unsupported_filter() {
+        let err = unsupported("link filter", &LinkFilter::Any);
+        assert_eq!(
+            err.to_string(),
+            "Invalid query: unsupported link filter Any"
+        );
+        let err = unsupported("data filter", &DataFilter::Unique);
+        assert_eq!(
+            err.to_string(),
+            "Invalid query: unsupported data filter Unique"
+        );
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn by_id() {
+        let db = test_db();
+        let stored = db.store(&Keyed.into_unique_random()).unwrap();
+        let entries = stored.entries().unwrap();
+        let key_id = entries[1].0.as_ref().unwrap().id();
+        let target_id = entries[1].1.id();
+
+        let query = Query::new(LinkFilter::Key(DataFilter::Id(key_id).into()));
+        assert_eq!(count_links(&stored, &query), 1);
+
+        let query = Query::new(LinkFilter::Target(DataFilter::Id(target_id).into()));
+        assert_eq!(count_links(&stored, &query), 1);
+
+        let query = Query::new(LinkFilter::Key(DataFilter::NotId(key_id).into()));
+        assert_eq!(count_links(&stored, &query), 1);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn id_in() {
+        let db = test_db();
+        let stored = db.store(&vec![0, 1, 2, 3, 4].into_unique_random()).unwrap();
+        let ids = stored
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(_, target)| target.id())
+            .collect::<Vec<_>>();
+
+        let mut sql = SQLBuilder::<()>::new_conjunct(());
+        sql.wher_id_in("`target_uuid`", [ids[0], ids[2], ids[4]]);
+        assert_eq!(sql.to_string(), "SELECT 1 WHERE `target_uuid` IN (?, ?, ?)");
+        assert_eq!(sql.params.len(), 3);
+
+        let any_of = DataFilter::Or(
+            vec![
+                DataFilter::Id(ids[0]),
+                DataFilter::Id(ids[2]),
+                DataFilter::Id(ids[4]),
+            ]
+            .into(),
+        );
+        let query = Query::new(LinkFilter::Target(any_of.into()));
+        assert_eq!(count_links(&stored, &query), 3);
+    }
+
+    #[test]
+    fn id_in_empty() {
+        let mut sql = SQLBuilder::<()>::new_conjunct(());
+        sql.wher_id_in("`target_uuid`", Vec::<ID>::new());
+        assert_eq!(sql.to_string(), "SELECT 1 WHERE 0");
+    }
 }
@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+
+/// Names of the tables and id columns used by a [`Database`](crate::database::Database).
+///
+/// All statements refer to these identifiers quoted with backticks
+/// and are rewritten to the configured names before being prepared.
+/// Value columns (`bool`, `str`, ...), indexes and triggers keep their names,
+/// so only one schema can be stored per database file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    pub values: String,
+    pub links: String,
+    pub uuid: String,
+    pub source_uuid: String,
+    pub key_uuid: String,
+    pub target_uuid: String,
+}
+
+impl Default for Schema {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            values: "values".into(),
+            links: "links".into(),
+            uuid: "uuid".into(),
+            source_uuid: "source_uuid".into(),
+            key_uuid: "key_uuid".into(),
+            target_uuid: "target_uuid".into(),
+        }
+    }
+}
+
+impl Schema {
+    #[inline]
+    fn rename(&self, ident: &str) -> Option<&str> {
+        let renamed = match ident {
+            "values" => &self.values,
+            "links" => &self.links,
+            "uuid" => &self.uuid,
+            "source_uuid" => &self.source_uuid,
+            "key_uuid" => &self.key_uuid,
+            "target_uuid" => &self.target_uuid,
+            _ => return None,
+        };
+        (renamed.as_str() != ident).then_some(renamed.as_str())
+    }
+
+    #[inline]
+    fn is_default(&self) -> bool {
+        [
+            "values",
+            "links",
+            "uuid",
+            "source_uuid",
+            "key_uuid",
+            "target_uuid",
+        ]
+        .into_iter()
+        .all(|ident| self.rename(ident).is_none())
+    }
+
+    /// Rewrites the backtick quoted default identifiers in `sql` to the names
+    /// of this schema.
+    #[must_use]
+    pub fn render<'sql>(&self, sql: &'sql str) -> Cow<'sql, str> {
+        if self.is_default() {
+            return Cow::Borrowed(sql);
+        }
+
+        let mut rendered = String::with_capacity(sql.len());
+        let mut rest = sql;
+        while let Some(start) = rest.find('`') {
+            let Some(len) = rest[start + 1..].find('`') else {
+                break;
+            };
+            let ident = &rest[start + 1..start + 1 + len];
+            rendered.push_str(&rest[..=start]);
+            rendered.push_str(self.rename(ident).unwrap_or(ident));
+            rendered.push('`');
+            rest = &rest[start + len + 2..];
+        }
+        rendered.push_str(rest);
+        Cow::Owned(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_default() {
+        let sql = "SELECT `uuid` FROM `values`";
+        assert!(matches!(Schema::default().render(sql), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn render_renamed() {
+        let schema = Schema {
+            values: "links".into(),
+            links: "values".into(),
+            ..Default::default()
+        };
+        let sql = "SELECT `values`.`str` FROM `values` JOIN `links` AS `links_t`";
+        assert_eq!(
+            schema.render(sql),
+            "SELECT `links`.`str` FROM `links` JOIN `values` AS `links_t`"
+        );
+    }
+}
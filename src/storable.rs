@@ -24,9 +24,10 @@ impl<D: Data + Unique> Storable for D {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::Database;
+    use crate::{database::Database, util::WithId};
     use datalink::data::DataExt;
 
+    #[cfg(feature = "random")]
     #[test]
     fn test_storable() {
         let db = Database::open_in_memory().unwrap();
@@ -35,4 +36,13 @@ mod tests {
         let stored = data.store(&db).unwrap();
         assert_eq!(stored.as_bool().unwrap(), true);
     }
+
+    #[test]
+    fn with_id() {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        let data = WithId::new(true, "1".parse().unwrap());
+        let stored = data.store(&db).unwrap();
+        assert_eq!(stored.as_bool().unwrap(), true);
+    }
 }
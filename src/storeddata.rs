@@ -6,8 +6,21 @@ use datalink::{
     value::{Provided, ValueQuery, ValueRequest},
 };
 
+use rusqlite::{
+    params,
+    types::{FromSql, ValueRef},
+};
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    fmt::Write,
+    iter::FusedIterator,
+    sync::Arc,
+};
+
 use crate::{
     database::Database,
+    error::{Error, Result},
+    primitive::{self, Primitive, PrimitiveType, PrimitiveValue},
     query::{build_links, QueryContext, SQLBuilder, SqlFragment},
     util::SqlID,
 };
@@ -18,6 +31,630 @@ pub struct StoredData {
     pub(crate) id: ID,
 }
 
+impl StoredData {
+    /// The id of this data, same as [`Unique::id`] without the trait in scope.
+    #[inline]
+    #[must_use]
+    pub fn id(&self) -> ID {
+        self.id
+    }
+
+    /// Returns the optional key and the target of every link of this data,
+    /// in insertion order.
+    #[inline]
+    pub fn entries(&self) -> Result<Vec<(Option<StoredData>, StoredData)>> {
+        const SQL: &str = "SELECT `key_uuid`, `target_uuid` FROM `links` WHERE `source_uuid` = ? ORDER BY `rowid`";
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+        let entries = stmt
+            .query_map([SqlID::from(self.id)], |r| {
+                let key = r.get::<_, Option<SqlID>>(0)?;
+                let target = r.get::<_, SqlID>(1)?;
+                Ok((
+                    key.map(|k| self.db.get(k.into())),
+                    self.db.get(target.into()),
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    /// Collects the keyed links of this data into a map from key id to target.
+    ///
+    /// Unkeyed links are skipped, and of several links with the same key
+    /// the most recently added one is kept.
+    #[inline]
+    pub fn as_map(&self) -> Result<HashMap<ID, StoredData>> {
+        const SQL: &str = "SELECT `key_uuid`, `target_uuid` FROM `links` WHERE `source_uuid` = ? AND `key_uuid` IS NOT NULL ORDER BY `rowid`";
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+        let map = stmt
+            .query_map([SqlID::from(self.id)], |r| {
+                let key = r.get::<_, SqlID>(0)?;
+                let target = r.get::<_, SqlID>(1)?;
+                Ok((ID::from(key), self.db.get(target.into())))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(map)
+    }
+
+    /// Returns the distinct keys of the links of this data, in order of first use.
+    #[inline]
+    pub fn keys(&self) -> Result<Vec<StoredData>> {
+        const SQL: &str = "SELECT `key_uuid` FROM `links` WHERE `source_uuid` = ? AND `key_uuid` IS NOT NULL GROUP BY `key_uuid` ORDER BY MIN(`rowid`)";
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+        let keys = stmt
+            .query_map([SqlID::from(self.id)], |r| {
+                Ok(self.db.get(r.get::<_, SqlID>(0)?.into()))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(keys)
+    }
+
+    /// Returns the distinct sources of all links targeting this data, in order of first link.
+    ///
+    /// Uses the `links_target` index, the reverse of [`StoredData::entries`].
+    #[inline]
+    pub fn referrers(&self) -> Result<Vec<StoredData>> {
+        const SQL: &str = "SELECT `source_uuid` FROM `links` WHERE `target_uuid` = ? GROUP BY `source_uuid` ORDER BY MIN(`rowid`)";
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+        let sources = stmt
+            .query_map([SqlID::from(self.id)], |r| {
+                Ok(self.db.get(r.get::<_, SqlID>(0)?.into()))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(sources)
+    }
+
+    /// Returns the targets of all links of this data keyed by `key`, in insertion order.
+    #[inline]
+    pub fn values_for_key(&self, key: &ID) -> Result<Vec<StoredData>> {
+        const SQL: &str = "SELECT `target_uuid` FROM `links` WHERE `source_uuid` = ? AND `key_uuid` = ? ORDER BY `rowid`";
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+        let targets = stmt
+            .query_map([SqlID::from(self.id), SqlID::from(*key)], |r| {
+                Ok(self.db.get(r.get::<_, SqlID>(0)?.into()))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(targets)
+    }
+
+    /// Returns the first non-NULL value of this data,
+    /// in the column order of [`PrimitiveType::ALL`](crate::primitive::PrimitiveType::ALL).
+    #[inline]
+    pub fn value(&self) -> Result<Option<Primitive>> {
+        let sql = format!(
+            "SELECT {} FROM `values` WHERE `uuid` = ?",
+            primitive::all_columns()
+        );
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let values = Primitive::from_row(row, 0)?;
+        Ok(values.into_iter().next())
+    }
+
+    /// Copies the values and links of this data to `other` in one transaction.
+    ///
+    /// Values of `other` are overwritten, the links are added to its existing ones.
+    /// Both have to belong to the same database, otherwise this fails with [`Error::ForeignData`].
+    #[inline]
+    pub fn merge_into(&self, other: &StoredData) -> Result {
+        const COPY_LINKS: &str = "INSERT INTO `links` (`source_uuid`, `key_uuid`, `target_uuid`)
+SELECT ?2, `key_uuid`, `target_uuid` FROM `links` WHERE `source_uuid` = ?1 ORDER BY `rowid`";
+
+        let copy_values = format!(
+            "INSERT INTO `values` (`uuid`, {0})
+SELECT ?2, {0} FROM `values` WHERE `uuid` = ?1
+ON CONFLICT(`uuid`)
+DO UPDATE
+SET {1}, updated_at=strftime('%Y-%m-%d %H:%M:%f', 'now');",
+            primitive::all_columns(),
+            primitive::set_all_excluded()
+        );
+
+        if !Arc::ptr_eq(&self.db.conn, &other.db.conn) {
+            return Err(Error::ForeignData);
+        }
+        let ids = [SqlID::from(self.id), SqlID::from(other.id)];
+        let schema = &self.db.schema;
+        self.db.check_writable()?;
+        self.db.transaction_with(|tx| {
+            tx.prepare_cached(&schema.render(&copy_values))?
+                .execute(ids)?;
+            tx.prepare_cached(&schema.render(COPY_LINKS))?
+                .execute(ids)?;
+            Ok(())
+        })
+    }
+
+    /// Formats the raw `values` row and `links` rows of this data for debugging.
+    ///
+    /// Ids are shown in their text form, NULL columns as `NULL`.
+    #[inline]
+    pub fn debug_dump(&self) -> Result<String> {
+        const VALUES: &str = "SELECT * FROM `values` WHERE `uuid` = ?";
+        const LINKS: &str = "SELECT * FROM `links` WHERE `source_uuid` = ? ORDER BY `rowid`";
+
+        let conn = self.db.lock()?;
+        let mut dump = format!("{}\n", self.id);
+
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(VALUES))?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(Into::into).collect();
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        match rows.next()? {
+            Some(row) => {
+                dump.push_str("values:\n");
+                for (i, name) in names.iter().enumerate() {
+                    let value = debug_value(row.get_ref(i)?);
+                    writeln!(dump, "  {name} = {value}").unwrap();
+                }
+            }
+            None => dump.push_str("values: no row\n"),
+        }
+        drop(rows);
+
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(LINKS))?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(Into::into).collect();
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        dump.push_str("links:\n");
+        while let Some(row) = rows.next()? {
+            dump.push(' ');
+            for (i, name) in names.iter().enumerate() {
+                let value = debug_value(row.get_ref(i)?);
+                write!(dump, " {name} = {value}").unwrap();
+            }
+            dump.push('\n');
+        }
+        Ok(dump)
+    }
+
+    /// Tells whether this data has a row of values, outgoing links, both or neither.
+    #[inline]
+    pub fn kind(&self) -> Result<DataKind> {
+        const SQL: &str = "SELECT EXISTS (SELECT 1 FROM `values` WHERE `uuid` = ?1), EXISTS (SELECT 1 FROM `links` WHERE `source_uuid` = ?1)";
+
+        let conn = self.db.lock()?;
+        let (values, links) = conn
+            .prepare_cached(&self.db.schema.render(SQL))?
+            .query_row([SqlID::from(self.id)], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        let kind = match (values, links) {
+            (true, true) => DataKind::Both,
+            (true, false) => DataKind::ValueOnly,
+            (false, true) => DataKind::LinksOnly,
+            (false, false) => DataKind::Absent,
+        };
+        Ok(kind)
+    }
+
+    /// Returns the types of all non-NULL values of this data,
+    /// in the order of [`PrimitiveType::ALL`].
+    #[inline]
+    pub fn stored_types(&self) -> Result<Vec<PrimitiveType>> {
+        let sql = format!(
+            "SELECT {} FROM `values` WHERE `uuid` = ?",
+            primitive::all_columns()
+        );
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        let Some(row) = rows.next()? else {
+            return Ok(Vec::new());
+        };
+        let mut types = Vec::new();
+        for (idx, ty) in PrimitiveType::ALL.into_iter().enumerate() {
+            if !matches!(row.get_ref(idx)?, ValueRef::Null) {
+                types.push(ty);
+            }
+        }
+        Ok(types)
+    }
+
+    /// Reads only the value of type `T`, without looking at any links.
+    #[inline]
+    pub fn query_one<T: PrimitiveValue>(&self) -> Result<Option<T>> {
+        let sql = format!(
+            "SELECT `{}` FROM `values` WHERE `uuid` = ?",
+            T::TYPE.column()
+        );
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let value = Primitive::from_sql(T::TYPE, row.get_ref(0)?)?;
+        Ok(value.and_then(T::from_primitive))
+    }
+
+    /// Hands the raw value of type `ty` to `f` without copying it,
+    /// `None` if there is no such value.
+    ///
+    /// The database stays locked while `f` runs.
+    #[inline]
+    pub fn with_value_ref<R>(
+        &self,
+        ty: PrimitiveType,
+        f: impl FnOnce(ValueRef) -> R,
+    ) -> Result<Option<R>> {
+        let sql = format!("SELECT `{}` FROM `values` WHERE `uuid` = ?", ty.column());
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        match row.get_ref(0)? {
+            ValueRef::Null => Ok(None),
+            value => Ok(Some(f(value))),
+        }
+    }
+
+    /// Reads the value of type `T` of every link target, in insertion order.
+    ///
+    /// Targets without such a value are skipped.
+    #[inline]
+    pub fn query_typed<T: PrimitiveValue>(&self) -> Result<Vec<T>> {
+        let sql = format!(
+            "SELECT `values`.`{}` FROM `links` JOIN `values` ON `values`.`uuid` = `links`.`target_uuid` WHERE `links`.`source_uuid` = ? ORDER BY `links`.`rowid`",
+            T::TYPE.column()
+        );
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        let mut rows = stmt.query([SqlID::from(self.id)])?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next()? {
+            let value = Primitive::from_sql(T::TYPE, row.get_ref(0)?)?;
+            values.extend(value.and_then(T::from_primitive));
+        }
+        Ok(values)
+    }
+
+    /// Replaces the values and links of this data with the ones of `data`, in one transaction.
+    ///
+    /// Unlike storing, nothing of the previous content is kept.
+    /// Links from other data to this one stay untouched.
+    #[inline]
+    pub fn replace<D: Data>(&self, data: &D) -> Result {
+        const DELETE_LINKS: &str = "DELETE FROM `links` WHERE `source_uuid` = ?";
+        const DELETE_VALUES: &str = "DELETE FROM `values` WHERE `uuid` = ?";
+
+        let db = &self.db;
+        let id = SqlID::from(self.id);
+        db.check_writable()?;
+        db.transaction_with(|tx| {
+            tx.execute(&db.schema.render(DELETE_LINKS), [id])?;
+            tx.execute(&db.schema.render(DELETE_VALUES), [id])?;
+            db.store_as(tx, self.id, data)
+        })
+    }
+
+    /// Sets the column of `value`'s type, keeping all other values.
+    #[inline]
+    pub fn set_value(&self, value: impl Into<Primitive>) -> Result {
+        let value = value.into();
+        let col = value.ty().column();
+        let sql = format!(
+            "INSERT INTO `values` (`uuid`, `{col}`) VALUES (?, ?)
+ON CONFLICT(`uuid`)
+DO UPDATE
+SET `{col}`=excluded.`{col}`, updated_at=strftime('%Y-%m-%d %H:%M:%f', 'now');"
+        );
+
+        self.db.check_writable()?;
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&sql))?;
+        stmt.execute(params![SqlID::from(self.id), value])?;
+        Ok(())
+    }
+
+    /// Removes one link to `target` keyed by `key`, or an unkeyed one if `key` is `None`.
+    ///
+    /// Returns whether a link was removed.
+    #[inline]
+    pub fn unlink(&self, key: Option<ID>, target: ID) -> Result<bool> {
+        // `IS` also matches a NULL key, which `=` never does
+        const SQL: &str = "DELETE FROM `links` WHERE `rowid` = (SELECT `rowid` FROM `links` WHERE `source_uuid` = ? AND `key_uuid` IS ? AND `target_uuid` = ? ORDER BY `rowid` LIMIT 1)";
+
+        self.db.check_writable()?;
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+        let removed = stmt.execute(params![
+            SqlID::from(self.id),
+            key.map(SqlID::from),
+            SqlID::from(target)
+        ])?;
+        Ok(removed > 0)
+    }
+
+    /// Lazily iterates the links of this data, see [`LinkIter`].
+    #[inline]
+    #[must_use]
+    pub fn iter_links(&self) -> LinkIter {
+        LinkIter {
+            data: self.clone(),
+            last_rowid: 0,
+            buffer: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Finds the shortest path over link targets from this data to `target`.
+    ///
+    /// The path starts with the id of this data and ends with `target`.
+    /// Returns `None` if `target` isn't reachable following at most `max_depth` links.
+    #[inline]
+    pub fn path_to(&self, target: impl Into<ID>, max_depth: usize) -> Result<Option<Vec<ID>>> {
+        const SQL: &str = "SELECT DISTINCT `target_uuid` FROM `links` WHERE `source_uuid` = ?";
+
+        let source = SqlID::from(self.id);
+        let target = SqlID::from(target.into());
+
+        let conn = self.db.lock()?;
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(SQL))?;
+
+        // Visited ids and the ids they were reached from, guards against cycles
+        let mut parents = HashMap::from([(source, source)]);
+        let mut frontier = vec![source];
+        let mut depth = 0;
+        while !parents.contains_key(&target) {
+            if depth == max_depth || frontier.is_empty() {
+                return Ok(None);
+            }
+            depth += 1;
+
+            let mut next = Vec::new();
+            for id in frontier {
+                let mut rows = stmt.query([id])?;
+                while let Some(r) = rows.next()? {
+                    if let Entry::Vacant(e) = parents.entry(r.get(0)?) {
+                        next.push(*e.key());
+                        e.insert(id);
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        let mut path = vec![target.into()];
+        let mut current = target;
+        while current != source {
+            current = parents[&current];
+            path.push(current.into());
+        }
+        path.reverse();
+        Ok(Some(path))
+    }
+
+    /// Copies this data and its direct link targets into memory.
+    ///
+    /// The copy is shallow: keys and targets come without their own links,
+    /// use [`StoredData::try_into_data_with_depth`] to follow links further.
+    #[inline]
+    pub fn try_into_data(&self) -> Result<InMemoryData> {
+        self.try_into_data_with_depth(1)
+    }
+
+    /// Copies this data into memory, following links `depth` levels deep.
+    ///
+    /// Data is copied once per link leading to it, so cycles are repeated
+    /// until `depth` is reached.
+    pub fn try_into_data_with_depth(&self, depth: usize) -> Result<InMemoryData> {
+        let owned = self.into_owned()?;
+        let links = match depth.checked_sub(1) {
+            None => Vec::new(),
+            Some(depth) => {
+                let copy = |id: ID| self.db.get(id).try_into_data_with_depth(depth);
+                owned
+                    .links
+                    .into_iter()
+                    .map(|(key, target)| Ok((key.map(copy).transpose()?, copy(target)?)))
+                    .collect::<Result<_>>()?
+            }
+        };
+        Ok(InMemoryData {
+            id: owned.id,
+            values: owned.values,
+            links,
+        })
+    }
+
+    /// Reads the values and links of this data into an [`OwnedData`],
+    /// which stays valid after the database is closed.
+    pub fn into_owned(&self) -> Result<OwnedData> {
+        const LINKS: &str = "SELECT `key_uuid`, `target_uuid` FROM `links` WHERE `source_uuid` = ? ORDER BY `rowid`";
+        let values = format!(
+            "SELECT {} FROM `values` WHERE `uuid` = ?",
+            primitive::all_columns()
+        );
+
+        let id = SqlID::from(self.id);
+        let conn = self.db.lock()?;
+
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(&values))?;
+        let mut rows = stmt.query([id])?;
+        let values = match rows.next()? {
+            Some(row) => Primitive::from_row(row, 0)?,
+            None => Vec::new(),
+        };
+
+        let mut stmt = conn.prepare_cached(&self.db.schema.render(LINKS))?;
+        let links = stmt
+            .query_map([id], |r| {
+                let key = r.get::<_, Option<SqlID>>(0)?;
+                let target = r.get::<_, SqlID>(1)?;
+                Ok((key.map(Into::into), target.into()))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(OwnedData {
+            id: self.id,
+            values,
+            links,
+        })
+    }
+}
+
+/// What is stored for an id, see [`StoredData::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    /// A row of values, possibly all NULL, and no links
+    ValueOnly,
+    /// Links but no row of values
+    LinksOnly,
+    Both,
+    /// Nothing, the id isn't stored
+    Absent,
+}
+
+/// Snapshot of a [`StoredData`] that doesn't refer to its database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedData {
+    pub id: ID,
+    /// The non-NULL values, in the column order of [`PrimitiveType::ALL`].
+    pub values: Vec<Primitive>,
+    /// The optional key and the target of every link, in insertion order.
+    pub links: Vec<(Option<ID>, ID)>,
+}
+
+/// In-memory copy of a [`StoredData`] and the data it links to,
+/// see [`StoredData::try_into_data`].
+///
+/// Implements [`Data`] and [`Unique`] with the original ids, so it can be
+/// stored into another backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InMemoryData {
+    pub id: ID,
+    pub values: Vec<Primitive>,
+    pub links: Vec<(Option<InMemoryData>, InMemoryData)>,
+}
+
+impl Data for InMemoryData {
+    #[inline]
+    fn provide_value(&self, request: &mut ValueRequest) {
+        for value in &self.values {
+            value.provide_to(request);
+        }
+    }
+
+    #[inline]
+    fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+        for (key, target) in &self.links {
+            let target = Box::new(target.clone());
+            match key {
+                Some(key) => links.push_keyed(target, Box::new(key.clone()))?,
+                None => links.push_unkeyed(target)?,
+            };
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn get_id(&self) -> Option<ID> {
+        Some(self.id)
+    }
+}
+
+impl Unique for InMemoryData {
+    #[inline]
+    fn id(&self) -> ID {
+        self.id
+    }
+}
+
+/// Formats a raw column value for [`StoredData::debug_dump`]
+fn debug_value(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".into(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => format!("{:?}", String::from_utf8_lossy(t)),
+        ValueRef::Blob(b) => match SqlID::column_result(value) {
+            Ok(id) => ID::from(id).to_string(),
+            Err(_) => {
+                b.iter().fold(String::from("x'"), |mut s, byte| {
+                    write!(s, "{byte:02x}").unwrap();
+                    s
+                }) + "'"
+            }
+        },
+    }
+}
+
+const LINK_PAGE_SIZE: usize = 256;
+
+/// Iterator over the optional key and the target of every link of a [`StoredData`].
+///
+/// Links are read in pages of bounded size and the connection is only locked
+/// while a page is fetched, so memory stays bounded for any number of links.
+#[derive(Debug)]
+pub struct LinkIter {
+    data: StoredData,
+    last_rowid: i64,
+    buffer: VecDeque<(Option<SqlID>, SqlID)>,
+    done: bool,
+}
+
+impl LinkIter {
+    fn fetch_page(&mut self) -> Result {
+        const SQL: &str = "SELECT `rowid`, `key_uuid`, `target_uuid` FROM `links` WHERE `source_uuid` = ? AND `rowid` > ? ORDER BY `rowid` LIMIT ?";
+
+        let db = &self.data.db;
+        let conn = db.lock()?;
+        let mut stmt = conn.prepare_cached(&db.schema.render(SQL))?;
+        let mut rows = stmt.query(params![
+            SqlID::from(self.data.id),
+            self.last_rowid,
+            LINK_PAGE_SIZE
+        ])?;
+
+        while let Some(r) = rows.next()? {
+            self.last_rowid = r.get(0)?;
+            self.buffer.push_back((r.get(1)?, r.get(2)?));
+        }
+        if self.buffer.len() < LINK_PAGE_SIZE {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for LinkIter {
+    type Item = Result<(Option<StoredData>, StoredData)>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            if let Err(e) = self.fetch_page() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let (key, target) = self.buffer.pop_front()?;
+        let db = &self.data.db;
+        Some(Ok((key.map(|k| db.get(k.into())), db.get(target.into()))))
+    }
+}
+
+impl FusedIterator for LinkIter {}
+
 impl Data for StoredData {
     #[inline]
     fn provide_value(&self, request: &mut ValueRequest) {
@@ -33,10 +670,19 @@ impl Data for StoredData {
         sql.wher("`uuid` = ?");
         sql.with(SqlID::from(self.id));
 
-        let conn = self.db.conn.lock().unwrap();
+        let conn = match self.db.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::error!("Failed to get values: {e}");
+                return;
+            }
+        };
         log::trace!("Running query: {:?}", &sql);
 
-        let mut stmt = sql.prepare_cached(&conn).unwrap();
+        let Ok(mut stmt) = sql.prepare_cached(&conn, &self.db.schema) else {
+            log::error!("Failed to prepare query: {sql:?}");
+            return;
+        };
         let Ok(mut rows) = stmt.query(sql.params()) else {
             log::error!("Failed to run query: {sql:?}");
             return;
@@ -54,7 +700,7 @@ impl Data for StoredData {
             }
         };
 
-        provide_selected(row, request, selected);
+        provide_selected(row, request, &selected);
     }
 
     #[inline]
@@ -67,12 +713,7 @@ impl Data for StoredData {
         // TODO: when Links provide a way to tell if they need key, target or both
         // we can optimize this query to only select and convert the needed columns to StoredData
 
-        let context = QueryContext {
-            table: "links".into(),
-            key_col: "key_uuid".into(),
-            target_col: "target_uuid".into(),
-        };
-        let mut sql = SQLBuilder::new_conjunct(context);
+        let mut sql = SQLBuilder::new_conjunct(QueryContext::links());
         // Ensure column #0 and #1 are the key and target IDs
         sql.select("`links`.`key_uuid`"); // Column #0
         sql.select("`links`.`target_uuid`"); // Column #1
@@ -108,118 +749,55 @@ impl Unique for StoredData {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Column {
-    Unused,
-    Bool,
-    U8,
-    I8,
-    U16,
-    I16,
-    U32,
-    I32,
-    U64,
-    I64,
-    F32,
-    F64,
-    Str,
-}
-
-#[allow(unused_assignments)] // last idx increment
-fn select_requested(sql: &mut SQLBuilder, requested: &impl TypeSet) -> [Column; 12] {
-    let mut selected: [Column; 12] = [Column::Unused; 12];
-    let mut idx = 0;
-
-    macro_rules! select {
-        ($sql:literal, $col:ident) => {
-            sql.select($sql);
-            selected[idx] = Column::$col;
-            idx += 1;
-        };
+/// Whether values of `ty` are part of `requested`
+fn is_requested(ty: PrimitiveType, requested: &impl TypeSet) -> bool {
+    use PrimitiveType as T;
+    match ty {
+        T::Bool => requested.contains_type::<bool>(),
+        T::U8 => requested.contains_type::<u8>(),
+        T::I8 => requested.contains_type::<i8>(),
+        T::U16 => requested.contains_type::<u16>(),
+        T::I16 => requested.contains_type::<i16>(),
+        T::U32 => requested.contains_type::<u32>(),
+        T::I32 => requested.contains_type::<i32>(),
+        T::U64 => requested.contains_type::<u64>(),
+        T::I64 => requested.contains_type::<i64>(),
+        T::F32 => requested.contains_type::<f32>(),
+        T::F64 => requested.contains_type::<f64>(),
+        T::Str => requested.contains_type::<&str>(),
     }
+}
 
-    if requested.contains_type::<bool>() {
-        select!("`values`.`bool` as `bool`", Bool);
-    }
-    if requested.contains_type::<u8>() {
-        select!("`values`.`u8` as `u8`", U8);
-    }
-    if requested.contains_type::<i8>() {
-        select!("`values`.`i8` as `i8`", I8);
-    }
-    if requested.contains_type::<u16>() {
-        select!("`values`.`u16` as `u16`", U16);
-    }
-    if requested.contains_type::<i16>() {
-        select!("`values`.`i16` as `i16`", I16);
-    }
-    if requested.contains_type::<u32>() {
-        select!("`values`.`u32` as `u32`", U32);
-    }
-    if requested.contains_type::<i32>() {
-        select!("`values`.`i32` as `i32`", I32);
-    }
-    if requested.contains_type::<u64>() {
-        select!("`values`.`u64` as `u64`", U64);
-    }
-    if requested.contains_type::<i64>() {
-        select!("`values`.`i64` as `i64`", I64);
-    }
-    if requested.contains_type::<f32>() {
-        select!("`values`.`f32` as `f32`", F32);
-    }
-    if requested.contains_type::<f64>() {
-        select!("`values`.`f64` as `f64`", F64);
-    }
-    if requested.contains_type::<&str>() {
-        select!("`values`.`str` as `str`", Str);
+/// Selects the columns of all requested types, returning them in the selected order
+fn select_requested(sql: &mut SQLBuilder, requested: &impl TypeSet) -> Vec<PrimitiveType> {
+    let selected: Vec<_> = PrimitiveType::ALL
+        .into_iter()
+        .filter(|ty| is_requested(*ty, requested))
+        .collect();
+    for ty in &selected {
+        let col = ty.column();
+        sql.select(format!("`values`.`{col}` as `{col}`"));
     }
-
     selected
 }
 
-#[allow(
-    clippy::cast_precision_loss,
-    clippy::cast_possible_truncation,
-    clippy::cast_sign_loss
-)]
-fn provide_selected<Q: ValueQuery, const C: usize>(
+fn provide_selected<Q: ValueQuery>(
     row: &rusqlite::Row,
     request: &mut ValueRequest<Q>,
-    selected: [Column; C],
+    selected: &[PrimitiveType],
 ) {
-    use rusqlite::types::ValueRef as V;
-    use Column as C;
-
-    for cell in selected
-        .into_iter()
-        .take_while(|c| *c != Column::Unused)
-        .enumerate()
-        .map(|(idx, col)| (col, row.get_ref(idx).unwrap()))
-    {
-        match cell {
-            (_, V::Null) => {}
-            (C::Bool, V::Integer(0)) => request.provide_bool(false),
-            (C::Bool, V::Integer(1)) => request.provide_bool(true),
-            (C::U8, V::Integer(i)) => request.provide_u8(i as u8),
-            (C::I8, V::Integer(i)) => request.provide_i8(i as i8),
-            (C::U16, V::Integer(i)) => request.provide_u16(i as u16),
-            (C::I16, V::Integer(i)) => request.provide_i16(i as i16),
-            (C::U32, V::Integer(i)) => request.provide_u32(i as u32),
-            (C::I32, V::Integer(i)) => request.provide_i32(i as i32),
-            (C::U64, V::Integer(i)) => request.provide_u64(i as u64),
-            (C::I64, V::Integer(i)) => request.provide_i64(i),
-            (C::F32, V::Real(f)) => request.provide_f32(f as f32),
-            (C::F32, V::Integer(i)) => request.provide_f32(i as f32),
-            (C::F64, V::Real(f)) => request.provide_f64(f),
-            (C::F64, V::Integer(i)) => request.provide_f64(i as f64),
-            (C::Str, V::Text(s)) => {
-                debug_assert!(std::str::from_utf8(s).is_ok());
-                request.provide_str(unsafe { std::str::from_utf8_unchecked(s) });
-            }
-            (c, v) => {
-                log::warn!("Unexpected value {v:?} for column {c:?}");
+    for (idx, ty) in selected.iter().enumerate() {
+        let cell = match row.get_ref(idx) {
+            Ok(cell) => cell,
+            Err(e) => {
+                log::warn!("Failed to read column {}: {e}", ty.column());
+                continue;
             }
+        };
+        match Primitive::from_sql(*ty, cell) {
+            Ok(Some(value)) => value.provide_to(request),
+            Ok(None) => {}
+            Err(_) => log::warn!("Unexpected value {cell:?} for column {}", ty.column()),
         }
     }
 }
@@ -228,9 +806,129 @@ fn provide_selected<Q: ValueQuery, const C: usize>(
 mod tests {
 
     use super::*;
-    use crate::database::Database;
+    use crate::{database::Database, util::WithId};
     use datalink::data::DataExt;
 
+    #[derive(Debug)]
+    struct Entries;
+
+    impl Data for Entries {
+        fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+            links.push_link(("a", 1))?;
+            links.push_link(("b", 2))?;
+            links.push_unkeyed(Box::new(3))?;
+            Ok(())
+        }
+    }
+
+    /// Links 1 -> "a", 2 -> "b", 1 -> "c" and an unkeyed "d"
+    #[derive(Debug)]
+    struct Multimap;
+
+    impl Data for Multimap {
+        fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+            let key = |id: &str| Box::new(WithId::new(id.to_owned(), id.parse().unwrap()));
+            links.push_keyed(Box::new("a"), key("1"))?;
+            links.push_keyed(Box::new("b"), key("2"))?;
+            links.push_keyed(Box::new("c"), key("1"))?;
+            links.push_unkeyed(Box::new("d"))?;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct AllValues;
+
+    impl Data for AllValues {
+        fn provide_value(&self, request: &mut ValueRequest) {
+            request.provide_bool(true);
+            request.provide_u8(8);
+            request.provide_i8(-8);
+            request.provide_u16(16);
+            request.provide_i16(-16);
+            request.provide_u32(32);
+            request.provide_i32(-32);
+            request.provide_u64(64);
+            request.provide_i64(-64);
+            request.provide_f32(32.5);
+            request.provide_f64(64.5);
+            request.provide_str("all");
+        }
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn try_into_data() {
+        let db = test_db();
+        let stored = db.store(&Entries.into_unique_random()).unwrap();
+        stored.set_value("entries").unwrap();
+        let copy = stored.try_into_data().unwrap();
+        drop(stored);
+        drop(db);
+
+        assert_eq!(copy.values, [Primitive::from("entries")]);
+        assert_eq!(copy.links.len(), 3);
+        let (key, target) = &copy.links[0];
+        assert_eq!(key.as_ref().unwrap().values, [Primitive::from("a")]);
+        assert_eq!(target.values, [Primitive::I32(1)]);
+
+        let other = test_db();
+        let stored = other.store(&copy).unwrap();
+        assert_eq!(stored.id(), copy.id);
+        assert_eq!(stored.as_str().as_deref(), Some("entries"));
+        let entries = stored.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].0.as_ref().unwrap().id(),
+            key.as_ref().unwrap().id
+        );
+        assert_eq!(entries[1].1.as_i32(), Some(2));
+        assert!(entries[2].0.is_none());
+        assert_eq!(stored.try_into_data().unwrap(), copy);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn into_owned() {
+        let db = test_db();
+        let stored = db.store(&Multimap.into_unique_random()).unwrap();
+        stored.set_value("map").unwrap();
+        let targets: Vec<ID> = stored
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|(_, t)| t.id)
+            .collect();
+
+        let owned = stored.into_owned().unwrap();
+        // Close the database, the snapshot doesn't need it
+        drop(stored);
+        drop(db);
+
+        assert_eq!(owned.values, vec![Primitive::from("map")]);
+        let one: ID = "1".parse().unwrap();
+        let two: ID = "2".parse().unwrap();
+        assert_eq!(
+            owned.links,
+            vec![
+                (Some(one), targets[0]),
+                (Some(two), targets[1]),
+                (Some(one), targets[2]),
+                (None, targets[3]),
+            ]
+        );
+    }
+
+    fn test_db() -> Database {
+        let db = Database::open_in_memory().unwrap();
+        db.init().unwrap();
+        // Linked data without an id needs some source of ids
+        #[cfg(not(feature = "random"))]
+        let db = db.with_random_seed(0);
+        db
+    }
+
+    #[cfg(feature = "random")]
     #[test]
     fn in_out() {
         let db = Database::open_in_memory().unwrap();
@@ -243,4 +941,476 @@ mod tests {
         assert_eq!(data_in.id(), data_out.id());
         assert_eq!(data_in.get_id(), data_out.get_id());
     }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn id() {
+        let db = test_db();
+        let stored = db.store(&"a".into_unique_random()).unwrap();
+        assert_eq!(stored.id(), stored.get_id().unwrap());
+        assert_eq!(stored.id(), Unique::id(&stored));
+        assert_eq!(db.get(stored.id()).id(), stored.id());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn entries() {
+        let db = test_db();
+        let stored = db.store(&Entries.into_unique_random()).unwrap();
+
+        let entries = stored.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let (key, target) = &entries[0];
+        assert_eq!(key.as_ref().unwrap().as_str().unwrap(), "a");
+        assert_eq!(target.as_i32().unwrap(), 1);
+
+        let (key, target) = &entries[1];
+        assert_eq!(key.as_ref().unwrap().as_str().unwrap(), "b");
+        assert_eq!(target.as_i32().unwrap(), 2);
+
+        let (key, target) = &entries[2];
+        assert!(key.is_none());
+        assert_eq!(target.as_i32().unwrap(), 3);
+    }
+
+    #[test]
+    fn path_to() {
+        let db = test_db();
+        let ids: Vec<ID> = ["1", "2", "3", "4", "5"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let conn = db.conn.lock().unwrap();
+        // 1 -> 2 -> 3 -> 4 -> 1, 1 -> 3
+        for (source, target) in [(0, 1), (1, 2), (2, 3), (3, 0), (0, 2)] {
+            conn.execute(
+                "INSERT INTO `links` (`source_uuid`, `target_uuid`) VALUES (?, ?)",
+                [SqlID::from(ids[source]), SqlID::from(ids[target])],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let first = db.get(ids[0]);
+        assert_eq!(first.path_to(ids[0], 0).unwrap(), Some(vec![ids[0]]));
+        assert_eq!(
+            first.path_to(ids[1], 1).unwrap(),
+            Some(vec![ids[0], ids[1]])
+        );
+        assert_eq!(
+            first.path_to(ids[3], 2).unwrap(),
+            Some(vec![ids[0], ids[2], ids[3]])
+        );
+        assert_eq!(first.path_to(ids[3], 1).unwrap(), None);
+        // Unreachable, terminates despite the cycle
+        assert_eq!(first.path_to(ids[4], 100).unwrap(), None);
+
+        let last = db.get(ids[3]);
+        assert_eq!(
+            last.path_to(ids[1], 5).unwrap(),
+            Some(vec![ids[3], ids[0], ids[1]])
+        );
+    }
+
+    #[test]
+    fn debug_dump() {
+        let db = test_db();
+        let stored = db.store(&Entries).unwrap();
+        let dump = db.get(stored.id()).debug_dump().unwrap();
+        assert!(dump.contains("links:"));
+        assert_eq!(dump.matches("target_uuid = ").count(), 3);
+
+        let target = &stored.entries().unwrap()[0].1;
+        let dump = target.debug_dump().unwrap();
+        assert!(dump.contains("i32 = 1\n"), "{dump}");
+        assert!(dump.contains("str = NULL\n"), "{dump}");
+        assert!(
+            dump.contains(&format!("uuid = {}\n", target.id())),
+            "{dump}"
+        );
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn merge_into() {
+        let db = test_db();
+        let source = db.store(&Entries).unwrap();
+        let value = db.store(&"value".into_unique_random()).unwrap();
+        let other = db.store(&vec![4].into_unique_random()).unwrap();
+
+        source.merge_into(&other).unwrap();
+        value.merge_into(&other).unwrap();
+
+        assert_eq!(other.as_str().as_deref(), Some("value"));
+        let entries = other.entries().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].1.as_i32(), Some(4));
+        assert_eq!(
+            entries[1].0.as_ref().unwrap().as_str().as_deref(),
+            Some("a")
+        );
+        assert_eq!(entries[3].1.as_i32(), Some(3));
+        // The source is unchanged
+        assert_eq!(source.entries().unwrap().len(), 3);
+
+        let foreign = test_db().store(&"foreign".into_unique_random()).unwrap();
+        let err = foreign.merge_into(&other).unwrap_err();
+        assert!(matches!(err, Error::ForeignData), "{err:?}");
+        assert_eq!(other.as_str().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn query_one() {
+        crate::test_util::capture_logs();
+        let db = test_db();
+        let stored = db.store(&AllValues).unwrap();
+        let empty = db.store(&Entries).unwrap();
+
+        db.set_trace(true).unwrap();
+        assert_eq!(stored.query_one::<i64>().unwrap(), Some(-64));
+        assert_eq!(empty.query_one::<i64>().unwrap(), None);
+        db.set_trace(false).unwrap();
+
+        let logs = crate::test_util::take_logs(crate::database::SQL_LOG_TARGET);
+        // Traced and profiled once per statement
+        assert_eq!(logs.len(), 4, "{logs:?}");
+        assert!(logs.iter().all(|l| !l.contains("`links`")), "{logs:?}");
+
+        assert_eq!(
+            stored.query_one::<String>().unwrap().as_deref(),
+            Some("all")
+        );
+        assert_eq!(
+            db.get("1".parse().unwrap()).query_one::<bool>().unwrap(),
+            None
+        );
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn bool_from_integer() {
+        let db = test_db();
+        let stored = db.store(&true.into_unique_random()).unwrap();
+        let conn = db.conn.lock().unwrap();
+        let raw: rusqlite::types::Value = conn
+            .query_row("SELECT `bool` FROM `values`", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(raw, rusqlite::types::Value::Integer(1));
+
+        // As written by other tools
+        const INSERT: &str = "INSERT INTO `values` (`uuid`, `bool`) VALUES (?, ?)";
+        let ids: Vec<ID> = ["1", "2", "3"].iter().map(|s| s.parse().unwrap()).collect();
+        conn.execute(INSERT, params![SqlID::from(ids[0]), 1])
+            .unwrap();
+        conn.execute(INSERT, params![SqlID::from(ids[1]), 2])
+            .unwrap();
+        conn.execute(INSERT, params![SqlID::from(ids[2]), "false"])
+            .unwrap();
+        drop(conn);
+
+        assert_eq!(stored.as_bool(), Some(true));
+        assert_eq!(db.get(ids[0]).as_bool(), Some(true));
+        assert_eq!(db.get(ids[0]).value().unwrap(), Some(Primitive::Bool(true)));
+        assert_eq!(db.get(ids[1]).query_one::<bool>().unwrap(), Some(true));
+        assert_eq!(db.get(ids[2]).as_bool(), Some(false));
+    }
+
+    #[test]
+    fn floats_roundtrip() {
+        let db = test_db();
+        let stored = db.get("1".parse().unwrap());
+
+        for f in [0.1, 1.0 / 3.0, f64::MIN_POSITIVE / 3.0, f64::MAX, -1.5] {
+            stored.set_value(f).unwrap();
+            let read = stored.query_one::<f64>().unwrap().unwrap();
+            assert_eq!(read.to_bits(), f.to_bits());
+            assert_eq!(read.to_string(), f.to_string());
+        }
+        for f in [0.1f32, 1.0 / 3.0, f32::MIN_POSITIVE / 3.0, f32::MAX] {
+            stored.set_value(f).unwrap();
+            let read = stored.query_one::<f32>().unwrap().unwrap();
+            assert_eq!(read.to_bits(), f.to_bits());
+            assert_eq!(read.to_string(), f.to_string());
+        }
+
+        // REAL columns store integral values as integers, losing the sign of zero
+        stored.set_value(-0.0f64).unwrap();
+        let read = stored.query_one::<f64>().unwrap().unwrap();
+        assert_eq!(read.to_bits(), 0.0f64.to_bits());
+        // and NaN is stored as NULL
+        stored.set_value(f64::NAN).unwrap();
+        assert_eq!(stored.query_one::<f64>().unwrap(), None);
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn kind() {
+        let db = test_db();
+        assert_eq!(
+            db.get("1".parse().unwrap()).kind().unwrap(),
+            DataKind::Absent
+        );
+
+        let value = db.store(&"a".into_unique_random()).unwrap();
+        assert_eq!(value.kind().unwrap(), DataKind::ValueOnly);
+
+        let both = db.store(&Entries.into_unique_random()).unwrap();
+        assert_eq!(both.kind().unwrap(), DataKind::Both);
+
+        let conn = db.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM `values` WHERE `uuid` = ?",
+            [SqlID::from(both.id())],
+        )
+        .unwrap();
+        drop(conn);
+        assert_eq!(both.kind().unwrap(), DataKind::LinksOnly);
+    }
+
+    #[test]
+    fn stored_types() {
+        let db = test_db();
+        let stored = db.get("1".parse().unwrap());
+        assert!(stored.stored_types().unwrap().is_empty());
+
+        stored.set_value("str").unwrap();
+        stored.set_value(64i64).unwrap();
+        assert_eq!(
+            stored.stored_types().unwrap(),
+            [PrimitiveType::I64, PrimitiveType::Str]
+        );
+
+        let all = db.store(&AllValues).unwrap();
+        assert_eq!(all.stored_types().unwrap(), PrimitiveType::ALL);
+    }
+
+    #[test]
+    fn with_value_ref() {
+        let db = test_db();
+        let stored = db.get("1".parse().unwrap());
+        assert_eq!(
+            stored.with_value_ref(PrimitiveType::Str, |_| ()).unwrap(),
+            None
+        );
+
+        stored.set_value("x".repeat(1 << 20)).unwrap();
+        let len = stored.with_value_ref(PrimitiveType::Str, |v| match v {
+            ValueRef::Text(t) => t.len(),
+            v => panic!("{v:?}"),
+        });
+        assert_eq!(len.unwrap(), Some(1 << 20));
+        assert_eq!(
+            stored.with_value_ref(PrimitiveType::I64, |_| ()).unwrap(),
+            None
+        );
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn query_typed() {
+        let db = test_db();
+        let stored = db.store(&vec![1i64, 2, 3].into_unique_random()).unwrap();
+        assert_eq!(stored.query_typed::<i64>().unwrap(), [1, 2, 3]);
+        assert!(stored.query_typed::<i32>().unwrap().is_empty());
+
+        stored.entries().unwrap()[1].1.set_value("two").unwrap();
+        assert_eq!(stored.query_typed::<String>().unwrap(), ["two"]);
+        assert_eq!(stored.query_typed::<i64>().unwrap(), [1, 2, 3]);
+
+        let mixed = db.store(&Entries.into_unique_random()).unwrap();
+        assert_eq!(mixed.query_typed::<i32>().unwrap(), [1, 2, 3]);
+        assert!(mixed.query_typed::<i64>().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn replace() {
+        let db = test_db();
+        let stored = db.store(&vec![1, 2, 3].into_unique_random()).unwrap();
+        stored.set_value("old").unwrap();
+
+        stored.replace(&vec![4, 5]).unwrap();
+        let list = stored.as_list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].as_i32(), Some(4));
+        assert_eq!(list[1].as_i32(), Some(5));
+        assert_eq!(stored.value().unwrap(), None);
+
+        stored.replace(&"new").unwrap();
+        assert!(stored.entries().unwrap().is_empty());
+        assert_eq!(stored.as_str().as_deref(), Some("new"));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn set_value() {
+        let db = test_db();
+        let stored = db.store(&true.into_unique_random()).unwrap();
+
+        stored.set_value(false).unwrap();
+        assert_eq!(stored.as_bool(), Some(false));
+        assert_eq!(stored.value().unwrap(), Some(Primitive::Bool(false)));
+
+        stored.set_value("set").unwrap();
+        assert_eq!(stored.as_str().as_deref(), Some("set"));
+        assert_eq!(stored.as_bool(), Some(false));
+
+        let new = db.get("1".parse().unwrap());
+        new.set_value(1u8).unwrap();
+        assert_eq!(new.value().unwrap(), Some(Primitive::U8(1)));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn unlink() {
+        let db = test_db();
+        let stored = db.store(&Multimap.into_unique_random()).unwrap();
+        let entries = stored.entries().unwrap();
+        let one: ID = "1".parse().unwrap();
+        let (a, d) = (entries[0].1.id, entries[3].1.id);
+
+        // Keyed, the key has to match
+        assert!(!stored.unlink(None, a).unwrap());
+        assert!(!stored.unlink(Some("2".parse().unwrap()), a).unwrap());
+        assert!(stored.unlink(Some(one), a).unwrap());
+        assert!(!stored.unlink(Some(one), a).unwrap());
+        assert_eq!(stored.entries().unwrap().len(), 3);
+        assert_eq!(stored.values_for_key(&one).unwrap().len(), 1);
+
+        // Unkeyed
+        assert!(!stored.unlink(Some(one), d).unwrap());
+        assert!(stored.unlink(None, d).unwrap());
+        let entries = stored.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|(key, _)| key.is_some()));
+    }
+
+    #[test]
+    fn referrers() {
+        /// Links to data with the given ids
+        #[derive(Debug)]
+        struct Targets(&'static [&'static str]);
+
+        impl Data for Targets {
+            fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+                for id in self.0 {
+                    links.push_unkeyed(Box::new(WithId::new(*id, id.parse().unwrap())))?;
+                }
+                Ok(())
+            }
+        }
+
+        let db = test_db();
+        let a = db
+            .store(&WithId::new(Targets(&["2", "2"]), "1".parse().unwrap()))
+            .unwrap();
+        let c = db
+            .store(&WithId::new(Targets(&["2"]), "3".parse().unwrap()))
+            .unwrap();
+
+        let b = db.get("2".parse().unwrap());
+        let referrers: Vec<_> = b.referrers().unwrap().iter().map(|r| r.id()).collect();
+        assert_eq!(referrers, [a.id(), c.id()]);
+        assert!(a.referrers().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn as_map() {
+        let db = test_db();
+        let stored = db.store(&Multimap.into_unique_random()).unwrap();
+
+        let map = stored.as_map().unwrap();
+        assert_eq!(map.len(), 2);
+        let get = |key: &str| map[&key.parse().unwrap()].as_str();
+        // "c" replaces "a"
+        assert_eq!(get("1").as_deref(), Some("c"));
+        assert_eq!(get("2").as_deref(), Some("b"));
+        assert!(!map.contains_key(&"3".parse().unwrap()));
+    }
+
+    #[test]
+    fn keys() {
+        let db = test_db();
+        let stored = db.store(&Multimap).unwrap();
+
+        let keys: Vec<_> = stored.keys().unwrap().iter().map(|k| k.id()).collect();
+        let ids: Vec<ID> = ["1", "2"].iter().map(|s| s.parse().unwrap()).collect();
+        assert_eq!(keys, ids);
+
+        let values = |key: &ID| -> Vec<_> {
+            stored
+                .values_for_key(key)
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(values(&ids[0]), ["a", "c"]);
+        assert_eq!(values(&ids[1]), ["b"]);
+        assert!(values(&"3".parse().unwrap()).is_empty());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn iter_links() {
+        let db = test_db();
+        let data = (0..10_000).collect::<Vec<i32>>().into_unique_random();
+        let stored = db.store(&data).unwrap();
+
+        let mut iter = stored.iter_links();
+        let mut count = 0;
+        while let Some(entry) = iter.next() {
+            let (key, target) = entry.unwrap();
+            assert!(key.is_none());
+            assert_eq!(target.as_i32().unwrap(), count);
+            assert!(iter.buffer.len() < LINK_PAGE_SIZE);
+            count += 1;
+        }
+
+        assert_eq!(count, 10_000);
+        assert!(iter.next().is_none());
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn all_columns() {
+        let db = test_db();
+        let stored = db.store(&AllValues.into_unique_random()).unwrap();
+
+        let values = stored.all_values();
+        assert_eq!(values.as_bool(), Some(true));
+        assert_eq!(values.as_u8(), Some(8));
+        assert_eq!(values.as_i8(), Some(-8));
+        assert_eq!(values.as_u16(), Some(16));
+        assert_eq!(values.as_i16(), Some(-16));
+        assert_eq!(values.as_u32(), Some(32));
+        assert_eq!(values.as_i32(), Some(-32));
+        assert_eq!(values.as_u64(), Some(64));
+        assert_eq!(values.as_i64(), Some(-64));
+        assert_eq!(values.as_f32(), Some(32.5));
+        assert_eq!(values.as_f64(), Some(64.5));
+        assert_eq!(values.as_str().as_deref(), Some("all"));
+
+        let copy = db.get("1".parse().unwrap());
+        stored.merge_into(&copy).unwrap();
+        assert_eq!(copy.stored_types().unwrap(), PrimitiveType::ALL);
+        assert_eq!(copy.all_values().as_f32(), Some(32.5));
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn value() {
+        let db = test_db();
+
+        let stored = db.store(&true.into_unique_random()).unwrap();
+        assert_eq!(stored.value().unwrap(), Some(Primitive::Bool(true)));
+
+        let stored = db.store(&"text".into_unique_random()).unwrap();
+        assert_eq!(stored.value().unwrap(), Some(Primitive::from("text")));
+
+        let stored = db.store(&Vec::<i32>::new().into_unique_random()).unwrap();
+        assert_eq!(stored.value().unwrap(), None);
+    }
 }
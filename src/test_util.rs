@@ -0,0 +1,54 @@
+use std::{
+    sync::{Mutex, Once},
+    thread::ThreadId,
+};
+
+static LOGS: Mutex<Vec<(ThreadId, &'static str, String)>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let target = match record.target() {
+            crate::database::SQL_LOG_TARGET => crate::database::SQL_LOG_TARGET,
+            _ => return,
+        };
+        let entry = (
+            std::thread::current().id(),
+            target,
+            record.args().to_string(),
+        );
+        LOGS.lock().unwrap().push(entry);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs a logger capturing the messages of the crate's log targets.
+pub fn capture_logs() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+}
+
+/// Removes and returns the messages logged to `target` by the current thread.
+pub fn take_logs(target: &str) -> Vec<String> {
+    let thread = std::thread::current().id();
+    let mut logs = LOGS.lock().unwrap();
+    let mut taken = Vec::new();
+    logs.retain(|(t, tgt, msg)| {
+        if *t == thread && *tgt == target {
+            taken.push(msg.clone());
+            false
+        } else {
+            true
+        }
+    });
+    taken
+}
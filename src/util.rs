@@ -1,8 +1,21 @@
-use datalink::id::ID;
+use datalink::{
+    id::ID,
+    links::{LinkError, Links},
+    prelude::*,
+    query::Query,
+    value::{Provided, ValueQuery, ValueRequest},
+};
 use rusqlite::{
-    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+    types::{FromSql, FromSqlResult, ToSqlOutput, Value, ValueRef},
     ToSql,
 };
+use std::{
+    hash::Hasher,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::{Error, Result};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
@@ -11,26 +24,35 @@ pub struct SqlID(ID);
 impl ToSql for SqlID {
     #[inline]
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
-        use rusqlite::types::Value;
         let value = Value::Blob(self.0.as_raw().get().to_be_bytes().into());
         Ok(ToSqlOutput::Owned(value))
     }
 }
 
+/// Reads the `N` bytes of a fixed-size blob from `column`.
+///
+/// Fails with [`Error::CorruptValue`] on blobs of any other size.
+pub(crate) fn decode_blob<const N: usize>(
+    column: &'static str,
+    value: ValueRef<'_>,
+) -> Result<[u8; N]> {
+    let bytes = value.as_blob()?;
+    bytes.try_into().map_err(|_| Error::CorruptValue {
+        column,
+        expected: N,
+        found: bytes.len(),
+    })
+}
+
+/// Ids are stored as 16 byte big-endian blobs.
 impl FromSql for SqlID {
     #[inline]
     fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
         use rusqlite::types::FromSqlError;
-        let bytes = value.as_blob()?;
-        let blob_size = bytes.len();
-        if blob_size != 16 {
-            return Err(FromSqlError::InvalidBlobSize {
-                expected_size: 16,
-                blob_size,
-            });
-        }
-        let mut array = [0; 16];
-        array.copy_from_slice(bytes);
+        let array = decode_blob::<16>("uuid", value).map_err(|e| match e {
+            Error::FromSql(e) => e,
+            e => FromSqlError::Other(Box::new(e)),
+        })?;
         let u128 = u128::from_be_bytes(array);
         if u128 == 0 {
             return Err(FromSqlError::OutOfRange(0));
@@ -53,3 +75,220 @@ impl From<SqlID> for ID {
         blob_id.0
     }
 }
+
+/// Deterministic source of random ids, see
+/// [`Database::with_random_seed`](crate::database::Database::with_random_seed).
+///
+/// Uses SplitMix64, which is fast and good enough for ids but not cryptographically secure.
+#[derive(Debug)]
+pub(crate) struct SeededIds(Mutex<u64>);
+
+impl SeededIds {
+    #[inline]
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(seed))
+    }
+
+    fn next_u64(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_id(&self) -> ID {
+        let mut state = self.0.lock().unwrap();
+        loop {
+            let high = u128::from(Self::next_u64(&mut state));
+            let raw = (high << 64) | u128::from(Self::next_u64(&mut state));
+            if raw != 0 {
+                // Safety: `raw` isn't 0
+                return unsafe { ID::new_unchecked(raw) };
+            }
+        }
+    }
+}
+
+/// The 64 bit FNV-1a hash, which unlike `DefaultHasher` is specified
+/// and the same on every platform and toolchain.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    #[inline]
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// How a [`Database`](crate::database::Database) mints ids for linked data without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdStrategy {
+    /// Fully random ids
+    #[default]
+    Random,
+    /// Ids starting with a millisecond timestamp followed by random bits,
+    /// like UUIDv7. Ids minted by this process are strictly increasing.
+    TimeOrdered,
+}
+
+impl IdStrategy {
+    /// Derives an id following this strategy from a `random` one.
+    #[must_use]
+    pub fn mint(self, random: ID) -> ID {
+        const RANDOM_BITS: u32 = 80;
+        static LAST: Mutex<u128> = Mutex::new(0);
+
+        match self {
+            Self::Random => random,
+            Self::TimeOrdered => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis());
+                let random = random.as_raw().get() & ((1 << RANDOM_BITS) - 1);
+                let mut raw = (millis << RANDOM_BITS) | random;
+
+                let mut last = LAST.lock().unwrap();
+                if raw <= *last {
+                    raw = *last + 1;
+                }
+                *last = raw;
+                // Safety: `raw` is greater than the initial 0 of `last`
+                unsafe { ID::new_unchecked(raw) }
+            }
+        }
+    }
+}
+
+/// Data with an explicitly assigned id.
+///
+/// Allows storing data without the `random` feature.
+#[derive(Debug, Clone)]
+pub struct WithId<D> {
+    pub data: D,
+    pub id: ID,
+}
+
+impl<D> WithId<D> {
+    #[inline]
+    #[must_use]
+    pub fn new(data: D, id: ID) -> Self {
+        Self { data, id }
+    }
+}
+
+impl<D: Data> Data for WithId<D> {
+    #[inline]
+    fn provide_value(&self, request: &mut ValueRequest) {
+        self.data.provide_value(request);
+    }
+
+    #[inline]
+    fn provide_requested<Q: ValueQuery>(&self, request: &mut ValueRequest<Q>) -> impl Provided {
+        self.data.provide_requested(request)
+    }
+
+    #[inline]
+    fn provide_links(&self, links: &mut dyn Links) -> Result<(), LinkError> {
+        self.data.provide_links(links)
+    }
+
+    #[inline]
+    fn query_links(&self, links: &mut dyn Links, query: &Query) -> Result<(), LinkError> {
+        self.data.query_links(links, query)
+    }
+
+    #[inline]
+    fn get_id(&self) -> Option<ID> {
+        Some(self.id)
+    }
+}
+
+impl<D: Data> Unique for WithId<D> {
+    #[inline]
+    fn id(&self) -> ID {
+        self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_id() -> SqlID {
+        "1234".parse::<ID>().unwrap().into()
+    }
+
+    #[test]
+    fn fnv1a() {
+        assert_eq!(Fnv1a::default().finish(), 0xcbf2_9ce4_8422_2325);
+        let mut hasher = Fnv1a::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn time_ordered() {
+        let raw = |id: ID| id.as_raw().get();
+        let random: ID = "1".parse().unwrap();
+        assert_eq!(IdStrategy::Random.mint(random), random);
+
+        let first = IdStrategy::TimeOrdered.mint("ffff".parse().unwrap());
+        let second = IdStrategy::TimeOrdered.mint(random);
+        assert!(raw(first) < raw(second));
+        assert!(raw(first) >> 80 > 0);
+    }
+
+    #[test]
+    fn blob_roundtrip() {
+        let id = test_id();
+        let Ok(ToSqlOutput::Owned(value)) = id.to_sql() else {
+            panic!("Ids are owned blobs");
+        };
+        assert!(matches!(value, Value::Blob(ref b) if b.len() == 16));
+
+        assert_eq!(SqlID::column_result(ValueRef::from(&value)).unwrap(), id);
+    }
+
+    #[test]
+    fn corrupt_blob() {
+        let err = decode_blob::<16>("uuid", ValueRef::Blob(&[1; 15])).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::CorruptValue {
+                    column: "uuid",
+                    expected: 16,
+                    found: 15
+                }
+            ),
+            "{err:?}"
+        );
+
+        let err = SqlID::column_result(ValueRef::Blob(&[1; 17])).unwrap_err();
+        assert!(err.to_string().contains("found 17"), "{err}");
+    }
+
+    #[test]
+    fn text_rejected() {
+        let text = ID::from(test_id()).to_string();
+        assert!(SqlID::column_result(ValueRef::Text(text.as_bytes())).is_err());
+    }
+}